@@ -1,34 +1,64 @@
+use crate::common::{RingBuffer, parse_size};
+use std::collections::VecDeque;
 use std::ffi::OsString;
-use std::fs::File;
-use std::io;
-use std::os::unix::fs::MetadataExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
 use std::os::unix::process::ExitStatusExt;
-use std::path::Path;
 use std::process::exit;
-use std::process::{Command, Stdio};
+use std::process::{ChildStderr, ChildStdout, Command, Stdio};
 use std::{env, process};
 
 fn usage() {
-    eprintln!("Usage: chronic [-ev] COMMAND...");
+    eprintln!("Usage: chronic [-ev] [--tail=SIZE] COMMAND...");
+    eprintln!("SIZE is a number of bytes, or of lines of up to 64K each when suffixed with l.");
+}
+
+// with --tail=Nl, at most N times this many bytes are kept, however long the lines are or
+// however long one goes on without a newline, as with \r progress bars
+const LINE_MAX: usize = 64 * 1024;
+
+// how much of each stream --tail keeps
+#[derive(Clone, Copy)]
+enum Limit {
+    Bytes(usize),
+    Lines(usize),
 }
 
 struct Args {
     verbose: bool,
     trigger_stderr: bool,
+    tail: Option<Limit>,
     command: OsString,
     arguments: Vec<OsString>,
 }
 
+fn parse_limit(value: &str) -> Option<Limit> {
+    let limit = match value.strip_suffix('l') {
+        Some(lines) => Limit::Lines(lines.parse().ok()?),
+        None => Limit::Bytes(usize::try_from(parse_size(value)?).ok()?),
+    };
+    match limit {
+        Limit::Bytes(0) | Limit::Lines(0) => None,
+        limit => Some(limit),
+    }
+}
+
 fn parse_args() -> Result<Args, lexopt::Error> {
     use lexopt::prelude::*;
     let mut verbose = false;
     let mut trigger_stderr = false;
+    let mut tail = None;
     let mut command: Option<OsString> = None;
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = parser.next()? {
         match arg {
             Short('v') => verbose = true,
             Short('e') => trigger_stderr = true,
+            Long("tail") => {
+                let value: String = parser.value()?.parse()?;
+                tail = Some(parse_limit(&value).ok_or(format!("invalid tail size: {value}"))?);
+            }
             Value(cmd) => {
                 command = Some(cmd);
                 break;
@@ -41,6 +71,7 @@ fn parse_args() -> Result<Args, lexopt::Error> {
     Ok(Args {
         verbose,
         trigger_stderr,
+        tail,
         command: command.ok_or("missing argument COMMAND")?,
         arguments,
     })
@@ -53,75 +84,320 @@ pub fn chronic() -> io::Result<()> {
         exit(1);
     });
 
-    let tmp_stdout_filename = env::temp_dir().join(format!("chronic_{}.out", std::process::id()));
-    let tmp_stderr_filename = env::temp_dir().join(format!("chronic_{}.err", std::process::id()));
-
-    let tmp_stdout = File::create(&tmp_stdout_filename)?;
-    let tmp_stderr = File::create(&tmp_stderr_filename)?;
-
-    let result = match Command::new(args.command)
+    let mut child = match Command::new(args.command)
         .args(args.arguments)
-        .stdout(Stdio::from(tmp_stdout))
-        .stderr(Stdio::from(tmp_stderr))
-        .status()
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
     {
-        Ok(ec) => ec,
+        Ok(child) => child,
         Err(e) => {
             eprintln!("Failed to execute command: {e}");
             process::exit(2);
         }
     };
+    let out = child.stdout.take().expect("stdout is piped");
+    let err = child.stderr.take().expect("stderr is piped");
+
+    let mut log = match args.tail {
+        Some(limit) => Log::Tails(Tail::new(limit), Tail::new(limit)),
+        None => Log::Journal(BufWriter::new(unlinked_tempfile()?)),
+    };
+    let mut seen_stderr = false;
+    capture(out, err, |stream, bytes| {
+        seen_stderr |= stream == Stream::Stderr;
+        log.record(stream, bytes)
+    })?;
+    let result = child.wait()?;
 
     match result.code() {
         Some(0) => {
-            if args.trigger_stderr && File::metadata(&File::open(&tmp_stderr_filename)?)?.size() > 0
-            {
-                output(args.verbose, 0, &tmp_stdout_filename, &tmp_stderr_filename)?;
+            if args.trigger_stderr && seen_stderr {
+                output(args.verbose, 0, log)?;
             }
             process::exit(0);
         }
         Some(code) => {
-            output(
-                args.verbose,
-                code,
-                &tmp_stdout_filename,
-                &tmp_stderr_filename,
-            )?;
+            output(args.verbose, code, log)?;
             process::exit(code);
         }
         None => {
             // Killed by signal?
             let code = result.signal().map(|s| 128 + s).unwrap_or(1);
-            output(
-                args.verbose,
-                code,
-                &tmp_stdout_filename,
-                &tmp_stderr_filename,
-            )?;
+            output(args.verbose, code, log)?;
             process::exit(1);
         }
     }
 }
 
-fn output(
-    verbose: bool,
-    code: i32,
-    stdout_filename: &Path,
-    stderr_filename: &Path,
+#[derive(Clone, Copy, PartialEq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn write(self, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            Stream::Stdout => {
+                // flushed every time so the two streams stay in order on a terminal
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(bytes)?;
+                stdout.flush()
+            }
+            Stream::Stderr => io::stderr().write_all(bytes),
+        }
+    }
+}
+
+// reads stdout and stderr until both are closed, handing each chunk on in the order it arrived
+fn capture(
+    out: ChildStdout,
+    err: ChildStderr,
+    mut record: impl FnMut(Stream, &[u8]) -> io::Result<()>,
 ) -> io::Result<()> {
-    let mut out = File::open(stdout_filename)?;
-    let mut err = File::open(stderr_filename)?;
-    let mut stdout = io::stdout();
-    let mut stderr = io::stderr();
-    if verbose {
-        println!("STDOUT:");
+    let mut open = [
+        Some((Stream::Stdout, File::from(OwnedFd::from(out)))),
+        Some((Stream::Stderr, File::from(OwnedFd::from(err)))),
+    ];
+    let mut buf = vec![0; 64 * 1024];
+    while open.iter().any(Option::is_some) {
+        let mut fds: Vec<libc::pollfd> = open
+            .iter()
+            .flatten()
+            .map(|(_, file)| libc::pollfd {
+                fd: file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        let mut ready = fds.iter().map(|fd| fd.revents != 0);
+        for slot in &mut open {
+            let Some((stream, file)) = slot else {
+                continue;
+            };
+            if !ready.next().unwrap_or(false) {
+                continue;
+            }
+            match file.read(&mut buf) {
+                Ok(0) => *slot = None,
+                Ok(n) => record(*stream, &buf[..n])?,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
     }
-    io::copy(&mut out, &mut stdout)?;
-    if verbose {
-        eprintln!();
-        eprintln!("STDERR:");
+    Ok(())
+}
+
+// unlinked as soon as it is created, so nothing is left behind however chronic exits
+fn unlinked_tempfile() -> io::Result<File> {
+    let mut attempt = 0;
+    loop {
+        let name = format!("chronic_{}_{attempt}", process::id());
+        let path = env::temp_dir().join(name);
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => {
+                fs::remove_file(&path)?;
+                return Ok(file);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
     }
-    io::copy(&mut err, &mut stderr)?;
+}
+
+// what the command wrote, held until chronic knows whether to show it
+enum Log {
+    // all of it, in an unlinked temporary file, as records of a stream byte, a length and the
+    // bytes themselves
+    Journal(BufWriter<File>),
+    // the end of stdout and the end of stderr
+    Tails(Tail, Tail),
+}
+
+impl Log {
+    fn record(&mut self, stream: Stream, bytes: &[u8]) -> io::Result<()> {
+        match self {
+            Log::Journal(journal) => {
+                journal.write_all(&[stream as u8])?;
+                journal.write_all(&(bytes.len() as u32).to_le_bytes())?;
+                journal.write_all(bytes)
+            }
+            Log::Tails(out, err) => {
+                let seq = out.pushed + err.pushed;
+                match stream {
+                    Stream::Stdout => out.push(seq, bytes),
+                    Stream::Stderr => err.push(seq, bytes),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // every chunk, in the order the command wrote them
+    fn replay(self, mut replay: impl FnMut(Stream, &[u8]) -> io::Result<()>) -> io::Result<()> {
+        match self {
+            Log::Journal(journal) => {
+                let mut file = journal.into_inner().map_err(|e| e.into_error())?;
+                file.rewind()?;
+                let mut reader = BufReader::new(file);
+                let mut header = [0; 5];
+                let mut bytes = Vec::new();
+                loop {
+                    match reader.read_exact(&mut header) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                        Err(e) => return Err(e),
+                    }
+                    let stream = match header[0] {
+                        0 => Stream::Stdout,
+                        _ => Stream::Stderr,
+                    };
+                    let len = u32::from_le_bytes(header[1..].try_into().unwrap());
+                    bytes.resize(len as usize, 0);
+                    reader.read_exact(&mut bytes)?;
+                    replay(stream, &bytes)?;
+                }
+            }
+            Log::Tails(out, err) => {
+                let mut chunks = out.into_chunks(Stream::Stdout);
+                chunks.extend(err.into_chunks(Stream::Stderr));
+                chunks.sort_by_key(|(seq, _, _)| *seq);
+                for (_, stream, bytes) in chunks {
+                    replay(stream, &bytes)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// the last bytes or lines of a stream, with the sequence number and length of each chunk they
+// came in, so the two streams can be merged again
+struct Tail {
+    kept: Kept,
+    chunks: VecDeque<(u64, usize)>,
+    len: usize,
+    pushed: u64,
+}
+
+enum Kept {
+    Bytes(RingBuffer, usize),
+    Lines {
+        data: VecDeque<u8>,
+        newlines: usize,
+        limit: usize,
+    },
+}
+
+impl Tail {
+    fn new(limit: Limit) -> Tail {
+        Tail {
+            kept: match limit {
+                Limit::Bytes(size) => Kept::Bytes(RingBuffer::new(size), size),
+                Limit::Lines(limit) => Kept::Lines {
+                    data: VecDeque::new(),
+                    newlines: 0,
+                    limit,
+                },
+            },
+            chunks: VecDeque::new(),
+            len: 0,
+            pushed: 0,
+        }
+    }
+
+    fn push(&mut self, seq: u64, bytes: &[u8]) {
+        self.pushed += 1;
+        self.chunks.push_back((seq, bytes.len()));
+        let total = self.len + bytes.len();
+        self.len = match &mut self.kept {
+            Kept::Bytes(ring, size) => {
+                ring.extend(bytes);
+                total.min(*size)
+            }
+            Kept::Lines {
+                data,
+                newlines,
+                limit,
+            } => {
+                data.extend(bytes);
+                *newlines += bytes.iter().filter(|&&b| b == b'\n').count();
+                let unterminated = data.back().is_some_and(|&b| b != b'\n');
+                while *newlines + unterminated as usize > *limit {
+                    let end = data.iter().position(|&b| b == b'\n').unwrap();
+                    data.drain(..=end);
+                    *newlines -= 1;
+                }
+                let excess = data.len().saturating_sub(limit.saturating_mul(LINE_MAX));
+                *newlines -= data.range(..excess).filter(|&&b| b == b'\n').count();
+                data.drain(..excess);
+                data.len()
+            }
+        };
+        // forget whatever has fallen off the front
+        let mut excess = total - self.len;
+        while excess > 0 {
+            let front = self.chunks.front_mut().unwrap();
+            if front.1 <= excess {
+                excess -= front.1;
+                self.chunks.pop_front();
+            } else {
+                front.1 -= excess;
+                excess = 0;
+            }
+        }
+    }
+
+    fn into_chunks(self, stream: Stream) -> Vec<(u64, Stream, Vec<u8>)> {
+        let data: Vec<u8> = match self.kept {
+            Kept::Bytes(ring, _) => ring.into_vec(),
+            Kept::Lines { data, .. } => data.into(),
+        };
+        let mut rest = &data[..];
+        self.chunks
+            .into_iter()
+            .map(|(seq, len)| {
+                let (bytes, tail) = rest.split_at(len);
+                rest = tail;
+                (seq, stream, bytes.to_vec())
+            })
+            .collect()
+    }
+}
+
+fn output(verbose: bool, code: i32, log: Log) -> io::Result<()> {
+    let mut last: Option<(Stream, bool)> = None;
+    log.replay(|stream, bytes| {
+        if verbose && last.map(|(s, _)| s) != Some(stream) {
+            // start the label on a line of its own
+            let newline = last.is_some_and(|(_, ended)| !ended);
+            let label = match stream {
+                Stream::Stdout => "STDOUT:\n",
+                Stream::Stderr => "STDERR:\n",
+            };
+            stream.write(
+                &[if newline { "\n" } else { "" }, label]
+                    .concat()
+                    .into_bytes(),
+            )?;
+        }
+        last = Some((stream, bytes.ends_with(b"\n")));
+        stream.write(bytes)
+    })?;
     if verbose {
         println!();
         println!("RETVAL: {code}");
@@ -48,19 +48,32 @@ impl RingBuffer {
             head: 0,
             size: 0,
             capacity: size,
-            data: vec![0; size],
+            data: Vec::new(),
         }
     }
 
     pub fn insert(&mut self, byte: u8) {
         self.size = min(self.capacity, self.size + 1);
-        self.data[self.head] = byte;
+        // grown as bytes arrive, so a large capacity costs nothing until it is filled
+        if self.data.len() < self.capacity {
+            self.data.push(byte);
+        } else {
+            self.data[self.head] = byte;
+        }
         self.head = (self.head + 1) % self.capacity;
     }
 
+    pub fn extend(&mut self, bytes: &[u8]) {
+        // only the last capacity bytes can survive
+        let skip = bytes.len().saturating_sub(self.capacity);
+        for byte in &bytes[skip..] {
+            self.insert(*byte);
+        }
+    }
+
     pub fn to_vec(&self) -> Vec<u8> {
         let mut vec = Vec::<u8>::with_capacity(self.size);
-        let start = (self.head - self.size + self.capacity) % self.capacity;
+        let start = (self.head + self.capacity - self.size) % self.capacity;
         let mut i = 0;
         while i < self.size {
             vec.push(self.data[(start + i) % self.capacity]);
@@ -133,3 +146,51 @@ impl<R: BufRead> OsLinesExt for R {
         }
     }
 }
+
+// accepts a byte count optionally suffixed with K, M, G or T (powers of 1024)
+// or k, m, g or t (powers of 1000), e.g. "512", "10M", "1.5G"
+pub fn parse_size(s: &str) -> Option<u64> {
+    let (number, multiplier) = match s.char_indices().last()? {
+        (i, 'K') => (&s[..i], 1u64 << 10),
+        (i, 'M') => (&s[..i], 1 << 20),
+        (i, 'G') => (&s[..i], 1 << 30),
+        (i, 'T') => (&s[..i], 1 << 40),
+        (i, 'k') => (&s[..i], 1_000),
+        (i, 'm') => (&s[..i], 1_000_000),
+        (i, 'g') => (&s[..i], 1_000_000_000),
+        (i, 't') => (&s[..i], 1_000_000_000_000),
+        _ => (s, 1),
+    };
+    if let Ok(n) = number.parse::<u64>() {
+        return n.checked_mul(multiplier);
+    }
+    let n = number.parse::<f64>().ok()? * multiplier as f64;
+    (n.is_finite() && n >= 0.0 && n < u64::MAX as f64).then_some(n as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(Some(512), parse_size("512"));
+        assert_eq!(Some(10 << 20), parse_size("10M"));
+        assert_eq!(Some(3 << 29), parse_size("1.5G"));
+        assert_eq!(Some(2_000), parse_size("2k"));
+        assert_eq!(None, parse_size("-1K"));
+        assert_eq!(None, parse_size("M"));
+        assert_eq!(None, parse_size("lots"));
+    }
+
+    #[test]
+    fn ring_extend() {
+        let mut ring = RingBuffer::new(4);
+        ring.extend(b"ab");
+        assert_eq!(b"ab".to_vec(), ring.to_vec());
+        ring.extend(b"cde");
+        assert_eq!(b"bcde".to_vec(), ring.to_vec());
+        ring.extend(b"0123456789");
+        assert_eq!(b"6789".to_vec(), ring.into_vec());
+    }
+}
@@ -1,27 +1,421 @@
+mod blocks;
+mod halt;
+mod joblog;
+mod progress;
+mod results;
+mod template;
+
+use std::collections::VecDeque;
 use std::env;
-use std::ffi::OsString;
-use std::io;
-use std::os::unix::ffi::OsStrExt;
-use std::os::unix::process::ExitStatusExt;
-use std::process::{Child, Command, exit};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio, exit};
+use std::sync::Arc;
+use std::sync::mpsc::TryRecvError;
 use std::thread;
-use std::time;
+use std::time::{Duration, Instant, SystemTime};
 
-use signal_hook::consts::SIGCHLD;
+use signal_hook::consts::{SIGCHLD, SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
 use signal_hook::iterator::Signals;
-use sysinfo::System;
+use signal_hook::low_level::pipe;
+use sysinfo::{MINIMUM_CPU_UPDATE_INTERVAL, System};
+
+use crate::common::parse_size;
+use blocks::Blocks;
+use halt::Halt;
+use joblog::JobLog;
+use progress::{Counts, Progress};
+use results::Results;
 
 fn usage() {
     eprintln!("parallel [OPTIONS] command -- arguments");
     eprintln!("        for each argument, run command iwth argument, in parallel");
+    eprintln!("parallel [OPTIONS] command ::: arguments [::: arguments | :::+ arguments]...");
+    eprintln!("        run command with every combination of the argument lists, where :::+");
+    eprintln!("        pairs a list up with the previous one instead");
     eprintln!("parallel [OPTIONS] -- commands");
     eprintln!("        run specified commands in parallel");
+    eprintln!();
+    eprintln!("  -i                replace {{}} in the command with the argument, and also");
+    eprintln!("                    {{.}} without extension, {{/}} basename, {{//}} dirname,");
+    eprintln!("                    {{/.}} basename without extension, {{#}} job number,");
+    eprintln!("                    {{%}} job slot, {{1}} {{2.}} ... the Nth argument of the job");
+    eprintln!("  -n N              pass N arguments from -- to each job");
+    eprintln!("  -j N              run up to N jobs at once");
+    eprintln!("  --jobs-file FILE  like -j, reading N from FILE again whenever it changes");
+    eprintln!("  -l LOAD           only start jobs while the load average is below LOAD");
+    eprintln!("  --joblog FILE     record each finished job in FILE");
+    eprintln!("  --results DIR     save job N's command, stdout, stderr and exitval in DIR/N/");
+    eprintln!("  --resume          skip jobs that FILE records as succeeded");
+    eprintln!("  --resume-failed   only run jobs that FILE records as failed");
+    eprintln!("  --retries N       retry a failing job up to N times");
+    eprintln!("  --retry-delay D   wait D before the first retry, doubling for each further one");
+    eprintln!("  --timeout D       kill jobs running longer than D, or N% of the median runtime");
+    eprintln!("  --halt WHEN,WHAT  stop early: soon|now, fail=N|fail=N%|success=N|success=N%");
+    eprintln!("  --memfree SIZE    only start jobs while SIZE bytes of memory are available");
+    eprintln!("  --memfree-kill    kill and requeue the youngest job below half of --memfree");
+    eprintln!("  --cpu-idle PCT    only start jobs while the CPUs are at least PCT% idle");
+    eprintln!("  --progress        show jobs done, running and left on stderr");
+    eprintln!("  --eta             like --progress, with the estimated time left");
+    eprintln!("  --pipe            split stdin into blocks of lines, each fed to one job");
+    eprintln!("  --block SIZE      make --pipe blocks about SIZE bytes, 1M by default");
+    eprintln!("  --dry-run         print the jobs as shell commands instead of running them");
+    eprintln!("  --shell           run command as shell code in $SHELL, quoting each argument");
+    eprintln!("  --shell-path SH   like --shell, using SH; also runs the commands of -- form");
+    eprintln!();
+    eprintln!("jobs see their job number in PARALLEL_SEQ and their slot, from 1 to -j, in");
+    eprintln!("PARALLEL_JOBSLOT.");
+    eprintln!("SIGUSR1 raises the number of jobs to run at once by one, SIGUSR2 lowers it.");
+    eprintln!("exits with the number of failed jobs, 101 if more than 100 failed");
 }
 
 #[derive(Debug)]
 struct Execution {
+    seq: usize,
     command: OsString,
     args: Vec<OsString>,
+    inputs: Vec<OsString>,
+    interpolate: bool,
+    // run the command words as shell code through this interpreter, quoting the inputs
+    shell: Option<OsString>,
+    // --pipe: the part of stdin to feed the job
+    block: Option<Arc<[u8]>>,
+    tries: u32,
+    not_before: Option<Instant>,
+}
+
+impl Execution {
+    fn argv(&self, slot: usize) -> Vec<OsString> {
+        let mut argv = Vec::with_capacity(1 + self.args.len() + self.inputs.len());
+        argv.push(self.command.clone());
+        argv.extend_from_slice(&self.args);
+        if self.interpolate {
+            let ctx = template::Context {
+                inputs: &self.inputs,
+                seq: self.seq,
+                slot,
+                quote: self.shell.is_some(),
+            };
+            argv = template::expand(&argv, &ctx);
+        } else if self.shell.is_some() {
+            for input in &self.inputs {
+                let mut quoted = Vec::new();
+                shell_quote(input, &mut quoted);
+                argv.push(OsString::from_vec(quoted));
+            }
+        } else {
+            argv.extend_from_slice(&self.inputs);
+        }
+        match &self.shell {
+            Some(shell) => {
+                let script = argv.join(OsStr::new(" "));
+                vec![shell.clone(), OsString::from("-c"), script]
+            }
+            None => argv,
+        }
+    }
+}
+
+fn command_line(argv: &[OsString]) -> String {
+    String::from_utf8_lossy(&quote_command_line(argv)).into_owned()
+}
+
+fn quote_command_line(argv: &[OsString]) -> Vec<u8> {
+    let mut line = Vec::new();
+    for (i, arg) in argv.iter().enumerate() {
+        if i > 0 {
+            line.push(b' ');
+        }
+        shell_quote(arg, &mut line);
+    }
+    line
+}
+
+// quotes arg for a POSIX shell, leaving it bare if that is already safe
+fn shell_quote(arg: &OsStr, out: &mut Vec<u8>) {
+    let bytes = arg.as_bytes();
+    let safe = |b: &u8| b.is_ascii_alphanumeric() || b"_@%+=:,./-".contains(b);
+    if !bytes.is_empty() && bytes.iter().all(safe) {
+        out.extend_from_slice(bytes);
+        return;
+    }
+    out.push(b'\'');
+    for b in bytes {
+        match b {
+            b'\'' => out.extend_from_slice(b"'\\''"),
+            b => out.push(*b),
+        }
+    }
+    out.push(b'\'');
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Resume {
+    No,
+    Unfinished,
+    Failed,
+}
+
+struct PoolOptions {
+    maxjobs: usize,
+    jobs_file: Option<PathBuf>,
+    maxload: Option<f64>,
+    retries: u32,
+    retry_delay: Duration,
+    timeout: Option<Timeout>,
+    halt: Option<Halt>,
+    progress: bool,
+    eta: bool,
+    memfree: Option<u64>,
+    memfree_kill: bool,
+    cpu_idle: Option<f32>,
+}
+
+// how often to check -l, --memfree, --cpu-idle and --jobs-file while waiting
+const RESOURCE_POLL: Duration = Duration::from_millis(500);
+
+struct Resources {
+    system: System,
+    cpu_refreshed: Instant,
+}
+
+impl Resources {
+    fn new() -> Resources {
+        let mut system = System::new();
+        system.refresh_cpu_usage();
+        Resources {
+            system,
+            cpu_refreshed: Instant::now(),
+        }
+    }
+
+    fn available(&mut self, options: &PoolOptions) -> bool {
+        if let Some(maxload) = options.maxload
+            && System::load_average().one >= maxload
+        {
+            return false;
+        }
+        if let Some(memfree) = options.memfree {
+            self.system.refresh_memory();
+            if self.system.available_memory() < memfree {
+                return false;
+            }
+        }
+        if let Some(cpu_idle) = options.cpu_idle {
+            // usage is measured between refreshes, which are meaningless when too close together
+            if self.cpu_refreshed.elapsed() >= MINIMUM_CPU_UPDATE_INTERVAL {
+                self.system.refresh_cpu_usage();
+                self.cpu_refreshed = Instant::now();
+            }
+            if 100.0 - self.system.global_cpu_usage() < cpu_idle {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn memory_critical(&mut self, options: &PoolOptions) -> bool {
+        match options.memfree {
+            Some(memfree) if options.memfree_kill => {
+                self.system.refresh_memory();
+                self.system.available_memory() < memfree / 2
+            }
+            _ => false,
+        }
+    }
+}
+
+// the number of jobs to run at once, which --jobs-file, SIGUSR1 and SIGUSR2 can change while
+// parallel is running
+struct JobLimit {
+    jobs: usize,
+    file: Option<PathBuf>,
+    modified: Option<SystemTime>,
+}
+
+impl JobLimit {
+    fn new(options: &PoolOptions) -> JobLimit {
+        JobLimit {
+            jobs: options.maxjobs,
+            file: options.jobs_file.clone(),
+            modified: options.jobs_file.as_deref().and_then(modified),
+        }
+    }
+
+    // re-reads the jobs file if it changed since it was last read
+    fn refresh(&mut self) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        let modified = modified(path);
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        match read_jobs_file(path) {
+            Some(jobs) => self.set(jobs),
+            None => eprintln!(
+                "parallel: ignoring {}, it does not hold a positive integer",
+                path.display()
+            ),
+        }
+    }
+
+    fn set(&mut self, jobs: usize) {
+        if jobs != self.jobs {
+            eprintln!("parallel: running up to {jobs} jobs at once");
+            self.jobs = jobs;
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn read_jobs_file(path: &Path) -> Option<usize> {
+    fs::read_to_string(path)
+        .ok()?
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|jobs| *jobs > 0)
+}
+
+// grace period between SIGTERM and SIGKILL for jobs that are killed
+const KILL_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Timeout {
+    Fixed(Duration),
+    Median(f64),
+}
+
+impl Timeout {
+    fn parse(s: &str) -> Option<Timeout> {
+        match s.strip_suffix('%') {
+            // anything past a million percent is not a real limit
+            Some(pct) => pct
+                .parse::<f64>()
+                .ok()
+                .filter(|pct| *pct > 0.0 && *pct <= 1e6)
+                .map(|pct| Timeout::Median(pct / 100.0)),
+            None => parse_duration(s).map(Timeout::Fixed),
+        }
+    }
+
+    // a percentage only takes effect once a few jobs have succeeded to take the median of
+    fn limit(&self, runtimes: &[Duration]) -> Option<Duration> {
+        match self {
+            Timeout::Fixed(limit) => Some(*limit),
+            Timeout::Median(_) if runtimes.len() < 3 => None,
+            Timeout::Median(factor) => {
+                let mut sorted = runtimes.to_vec();
+                sorted.sort();
+                Duration::try_from_secs_f64(sorted[sorted.len() / 2].as_secs_f64() * factor).ok()
+            }
+        }
+    }
+}
+
+struct Running {
+    child: Child,
+    job: Execution,
+    argv: Vec<OsString>,
+    slot: usize,
+    start: SystemTime,
+    started: Instant,
+    terminated: Option<Instant>,
+    requeue: bool,
+    stopped: bool,
+}
+
+impl Running {
+    fn deadline(&self, limit: Option<Duration>) -> Option<Instant> {
+        match self.terminated {
+            Some(terminated) => Some(terminated + KILL_GRACE),
+            // a limit too far off to reach is no limit at all
+            None => limit.and_then(|limit| self.started.checked_add(limit)),
+        }
+    }
+
+    fn terminate(&mut self) {
+        if self.terminated.is_none() {
+            self.kill(libc::SIGTERM);
+            self.terminated = Some(Instant::now());
+        }
+    }
+
+    fn escalate(&mut self) {
+        if self
+            .terminated
+            .is_some_and(|terminated| terminated.elapsed() >= KILL_GRACE)
+        {
+            self.kill(libc::SIGKILL);
+        }
+    }
+
+    // the signal that stopped the job, such as SIGTTIN when it reads the terminal from outside
+    // the foreground process group; try_wait never reports these, and WNOWAIT leaves the state
+    // for it to collect later
+    fn stopped_by(&self) -> Option<libc::c_int> {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let flags = libc::WSTOPPED | libc::WNOHANG | libc::WNOWAIT;
+        let found = unsafe { libc::waitid(libc::P_PID, self.child.id(), &mut info, flags) } == 0;
+        if found && unsafe { info.si_pid() } != 0 && info.si_code == libc::CLD_STOPPED {
+            Some(unsafe { info.si_status() })
+        } else {
+            None
+        }
+    }
+
+    // every job leads its own process group, so this reaches everything it spawned
+    fn kill(&self, signal: libc::c_int) {
+        unsafe { libc::kill(-(self.child.id() as libc::pid_t), signal) };
+    }
+}
+
+// the jobs the pool is running; nothing else can reach them in their own process groups, so if
+// pool_jobs returns early with an error, dropping them terminates and reaps every one
+struct Jobs(Vec<Running>);
+
+impl Deref for Jobs {
+    type Target = Vec<Running>;
+
+    fn deref(&self) -> &Vec<Running> {
+        &self.0
+    }
+}
+
+impl DerefMut for Jobs {
+    fn deref_mut(&mut self) -> &mut Vec<Running> {
+        &mut self.0
+    }
+}
+
+impl Drop for Jobs {
+    fn drop(&mut self) {
+        for running in &mut self.0 {
+            running.terminate();
+            running.kill(libc::SIGCONT);
+        }
+        let deadline = Instant::now() + KILL_GRACE;
+        while !self.0.is_empty() && Instant::now() < deadline {
+            self.0
+                .retain_mut(|running| matches!(running.child.try_wait(), Ok(None)));
+            thread::sleep(Duration::from_millis(10));
+        }
+        for running in &mut self.0 {
+            running.kill(libc::SIGKILL);
+            let _ = running.child.wait();
+        }
+    }
 }
 
 pub fn parallel() -> io::Result<()> {
@@ -29,6 +423,24 @@ pub fn parallel() -> io::Result<()> {
     let mut n_args: usize = 1;
     let mut maxload: Option<f64> = None;
     let mut maxjobs = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut joblog: Option<PathBuf> = None;
+    let mut resume = Resume::No;
+    let mut retries: u32 = 0;
+    let mut retry_delay = Duration::ZERO;
+    let mut timeout: Option<Timeout> = None;
+    let mut halt: Option<Halt> = None;
+    let mut progress = false;
+    let mut eta = false;
+    let mut dry_run = false;
+    let mut jobs_file = None;
+    let mut results = None;
+    let mut pipe = false;
+    let mut block_size = None;
+    let mut use_shell = false;
+    let mut shell_path = None;
+    let mut memfree: Option<u64> = None;
+    let mut memfree_kill = false;
+    let mut cpu_idle: Option<f32> = None;
 
     let mut args = env::args_os().skip(1).peekable();
 
@@ -72,6 +484,110 @@ pub fn parallel() -> io::Result<()> {
                         exit(1);
                     })
             }
+            Some("--jobs-file") => {
+                jobs_file = Some(PathBuf::from(args.next().unwrap_or_else(|| {
+                    eprintln!("parallel: --jobs-file requires a file argument");
+                    exit(1);
+                })))
+            }
+            Some("--joblog") => {
+                joblog = Some(PathBuf::from(args.next().unwrap_or_else(|| {
+                    eprintln!("parallel: --joblog requires a file argument");
+                    exit(1);
+                })))
+            }
+            Some("--results") => {
+                results = Some(PathBuf::from(args.next().unwrap_or_else(|| {
+                    eprintln!("parallel: --results requires a directory argument");
+                    exit(1);
+                })))
+            }
+            Some("--resume") => resume = Resume::Unfinished,
+            Some("--resume-failed") => resume = Resume::Failed,
+            Some("--retries") => {
+                retries = args
+                    .next()
+                    .and_then(|os_str| os_str.to_str().and_then(|s| s.parse::<u32>().ok()))
+                    .unwrap_or_else(|| {
+                        eprintln!("parallel: --retries requires a positive integer argument");
+                        exit(1);
+                    })
+            }
+            Some("--retry-delay") => {
+                retry_delay = args
+                    .next()
+                    .and_then(|os_str| os_str.to_str().and_then(parse_duration))
+                    .unwrap_or_else(|| {
+                        eprintln!("parallel: --retry-delay requires a duration argument");
+                        exit(1);
+                    })
+            }
+            Some("--timeout") => {
+                timeout = Some(
+                    args.next()
+                        .and_then(|os_str| os_str.to_str().and_then(Timeout::parse))
+                        .unwrap_or_else(|| {
+                            eprintln!(
+                                "parallel: --timeout requires a duration or percentage argument"
+                            );
+                            exit(1);
+                        }),
+                )
+            }
+            Some("--memfree") => {
+                memfree = Some(
+                    args.next()
+                        .and_then(|os_str| os_str.to_str().and_then(parse_size))
+                        .unwrap_or_else(|| {
+                            eprintln!("parallel: --memfree requires a size argument");
+                            exit(1);
+                        }),
+                )
+            }
+            Some("--memfree-kill") => memfree_kill = true,
+            Some("--cpu-idle") => {
+                cpu_idle = Some(
+                    args.next()
+                        .and_then(|os_str| os_str.to_str().and_then(|s| s.parse::<f32>().ok()))
+                        .filter(|pct| (0.0..=100.0).contains(pct))
+                        .unwrap_or_else(|| {
+                            eprintln!("parallel: --cpu-idle requires a percentage argument");
+                            exit(1);
+                        }),
+                )
+            }
+            Some("--progress") => progress = true,
+            Some("--eta") => eta = true,
+            Some("--dry-run") => dry_run = true,
+            Some("--pipe") => pipe = true,
+            Some("--block") => {
+                block_size = Some(
+                    args.next()
+                        .and_then(|os_str| os_str.to_str().and_then(parse_size))
+                        .and_then(|size| usize::try_from(size).ok())
+                        .filter(|size| *size > 0)
+                        .unwrap_or_else(|| {
+                            eprintln!("parallel: --block requires a size argument");
+                            exit(1);
+                        }),
+                )
+            }
+            Some("--shell") => use_shell = true,
+            Some("--shell-path") => {
+                shell_path = Some(args.next().unwrap_or_else(|| {
+                    eprintln!("parallel: --shell-path requires a file argument");
+                    exit(1);
+                }))
+            }
+            Some("--halt") => {
+                halt = match args.next().as_ref().and_then(|os_str| os_str.to_str()) {
+                    Some("never" | "0") => None,
+                    value => Some(value.and_then(Halt::parse).unwrap_or_else(|| {
+                        eprintln!("parallel: --halt requires a policy such as soon,fail=1");
+                        exit(1);
+                    })),
+                }
+            }
             _ => {
                 eprintln!("parallel: invalid option -- '{}'", arg.display());
                 usage();
@@ -80,26 +596,80 @@ pub fn parallel() -> io::Result<()> {
         }
     }
 
-    let jobs: Vec<Execution> = match args.peek().and_then(|a| a.to_str()) {
+    if memfree_kill && memfree.is_none() {
+        eprintln!("parallel: --memfree-kill requires --memfree");
+        exit(1);
+    }
+
+    if let Some(path) = &jobs_file {
+        maxjobs = read_jobs_file(path).unwrap_or_else(|| {
+            eprintln!(
+                "parallel: --jobs-file {} must hold a positive integer",
+                path.display()
+            );
+            exit(1);
+        });
+    }
+
+    if block_size.is_some() && !pipe {
+        eprintln!("parallel: --block requires --pipe");
+        exit(1);
+    }
+
+    if pipe && dry_run {
+        eprintln!("parallel: --dry-run cannot be used with --pipe");
+        exit(1);
+    }
+
+    if resume != Resume::No && joblog.is_none() {
+        eprintln!("parallel: --resume and --resume-failed require --joblog");
+        exit(1);
+    }
+
+    let mut jobs: Vec<Execution> = match args.peek().and_then(|a| a.to_str()) {
         Some("--") => args
             .skip(1) // skip --
-            .map(|a| Execution {
-                command: OsString::from("sh"),
+            .enumerate()
+            .map(|(i, a)| Execution {
+                seq: i + 1,
+                command: shell_path.clone().unwrap_or_else(|| OsString::from("sh")),
                 args: vec![OsString::from("-c"), a],
+                inputs: Vec::new(),
+                interpolate,
+                shell: None,
+                block: None,
+                tries: 0,
+                not_before: None,
             })
             .collect(),
         Some(_) => {
             let command = args.next().expect("peek was a Some value");
-            let (fixed_args, parallel_args) = split_args(args);
-            parallel_args
-                .chunks(n_args)
-                .map(|chunk| {
-                    let mut fa = fixed_args.clone();
-                    fa.extend_from_slice(chunk);
-                    Execution {
-                        command: command.clone(),
-                        args: fa,
-                    }
+            let shell = (use_shell || shell_path.is_some()).then(|| {
+                shell_path
+                    .clone()
+                    .or_else(|| env::var_os("SHELL"))
+                    .unwrap_or_else(|| OsString::from("sh"))
+            });
+            let (fixed_args, arguments) = split_args(args);
+            let inputs: Vec<Vec<OsString>> = match arguments {
+                // --pipe runs the command once per block of stdin instead
+                Arguments::List(list) if pipe && list.is_empty() => vec![Vec::new()],
+                Arguments::List(list) => list.chunks(n_args).map(<[OsString]>::to_vec).collect(),
+                Arguments::Sources(groups) => combinations(&groups),
+            };
+            inputs
+                .into_iter()
+                .enumerate()
+                .map(|(i, chunk)| Execution {
+                    seq: i + 1,
+                    command: command.clone(),
+                    args: fixed_args.clone(),
+                    inputs: chunk,
+                    interpolate,
+                    shell: shell.clone(),
+                    block: None,
+                    tries: 0,
+                    not_before: None,
                 })
                 .collect()
         }
@@ -109,74 +679,551 @@ pub fn parallel() -> io::Result<()> {
         }
     };
 
-    println!("-i {interpolate} -l {maxload:?} -j {maxjobs:?} -n {n_args}");
-    for e in jobs.iter() {
-        println!("{e:?}");
-    }
-
-    exit(pool_jobs(maxjobs, maxload, jobs)?);
-}
-
-fn pool_jobs(maxjobs: usize, maxload: Option<f64>, jobs: Vec<Execution>) -> io::Result<i32> {
-    let mut exit_code = 0;
-    let mut jobs_running: Vec<Child> = Vec::new();
-    let mut binding = Signals::new([SIGCHLD])?;
-    let mut signals = binding.forever();
-    for job in jobs {
-        if jobs_running.len() == maxjobs {
-            match signals.next() {
-                Some(SIGCHLD) => {
-                    jobs_running.retain_mut(|child| match child.try_wait() {
-                        Ok(None) => true,
-                        Ok(Some(status)) => {
-                            exit_code |= match status.code() {
-                                Some(code) => code,
-                                None => status.signal().map_or_else(|| 1, |sig| 128 + sig),
-                            };
-                            false
-                        }
-                        Err(_) => true, // ignored, try_wait again later for this process
-                    });
+    let pipe = match pipe {
+        true if jobs.len() != 1 => {
+            eprintln!("parallel: --pipe runs a single command");
+            exit(1);
+        }
+        true => Some((jobs.remove(0), block_size.unwrap_or(1024 * 1024))),
+        false => None,
+    };
+
+    if let Some(path) = &joblog
+        && resume != Resume::No
+    {
+        let outcomes = joblog::read_outcomes(path)?;
+        jobs.retain(|job| match (resume, outcomes.get(&job.seq)) {
+            (Resume::Failed, outcome) => outcome == Some(&false),
+            (_, outcome) => outcome != Some(&true),
+        });
+    }
+
+    if dry_run {
+        let mut stdout = io::stdout().lock();
+        for job in &jobs {
+            // the slot the job would get if every job took equally long
+            let slot = (job.seq - 1) % maxjobs.max(1) + 1;
+            let mut line = quote_command_line(&job.argv(slot));
+            line.push(b'\n');
+            stdout.write_all(&line)?;
+        }
+        return Ok(());
+    }
+
+    let joblog = match &joblog {
+        Some(path) => Some(JobLog::open(path, resume != Resume::No)?),
+        None => None,
+    };
+
+    let options = PoolOptions {
+        maxjobs,
+        jobs_file,
+        maxload,
+        retries,
+        retry_delay,
+        timeout,
+        halt,
+        progress,
+        eta,
+        memfree,
+        memfree_kill,
+        cpu_idle,
+    };
+    let results = match &results {
+        Some(dir) => Some(Results::create(dir)?),
+        None => None,
+    };
+
+    exit(pool_jobs(&options, joblog, results, jobs, pipe)?);
+}
+
+fn pool_jobs(
+    options: &PoolOptions,
+    mut joblog: Option<JobLog>,
+    results: Option<Results>,
+    jobs: Vec<Execution>,
+    pipe: Option<(Execution, usize)>,
+) -> io::Result<i32> {
+    let mut total = jobs.len();
+    let mut succeeded = 0;
+    let mut failed = 0;
+    let mut halted: Option<i32> = None;
+    let mut queue: VecDeque<Execution> = jobs.into();
+    let mut jobs_running = Jobs(Vec::new());
+    let mut runtimes: Vec<Duration> = Vec::new();
+    let mut busy = Duration::ZERO;
+    let mut progress = (options.progress || options.eta).then(|| Progress::new(options.eta));
+    let mut resources = Resources::new();
+    let mut limit = JobLimit::new(options);
+    let tty = io::stdin().is_terminal();
+    // SIGCHLD, SIGHUP, SIGINT, SIGTERM, SIGUSR1 and SIGUSR2 all wake the pool up through the same
+    // socket
+    let (mut wake, wake_tx) = UnixStream::pair()?;
+    let mut blocks = match pipe {
+        Some((template, size)) => Some(Blocks::spawn(template, size, wake_tx.try_clone()?)),
+        None => None,
+    };
+    let mut interrupts = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+    let mut adjustments = Signals::new([SIGUSR1, SIGUSR2])?;
+    let wake_ids = [
+        pipe::register(SIGHUP, wake_tx.try_clone()?)?,
+        pipe::register(SIGINT, wake_tx.try_clone()?)?,
+        pipe::register(SIGTERM, wake_tx.try_clone()?)?,
+        pipe::register(SIGUSR1, wake_tx.try_clone()?)?,
+        pipe::register(SIGUSR2, wake_tx.try_clone()?)?,
+        pipe::register(SIGCHLD, wake_tx)?,
+    ];
+    loop {
+        for signal in adjustments.pending() {
+            match signal {
+                SIGUSR1 => limit.set(limit.jobs + 1),
+                _ => limit.set(limit.jobs.saturating_sub(1).max(1)),
+            }
+        }
+        limit.refresh();
+
+        for signal in interrupts.pending() {
+            if halted.is_none() {
+                eprintln!(
+                    "parallel: interrupted, waiting for {} running jobs",
+                    jobs_running.len()
+                );
+                halted = Some(128 + signal);
+                queue.clear();
+                blocks = None;
+                for running in jobs_running.iter() {
+                    running.kill(signal);
                 }
-                _ => unreachable!("we only register a SIGCHLD handler"),
+            } else {
+                eprintln!("parallel: killing {} running jobs", jobs_running.len());
+                for running in jobs_running.iter() {
+                    running.kill(libc::SIGKILL);
+                }
+            }
+        }
+
+        let timeout = options.timeout.and_then(|t| t.limit(&runtimes));
+        if jobs_running.len() > 1
+            && !jobs_running.iter().any(|running| running.requeue)
+            && resources.memory_critical(options)
+            && let Some(youngest) = jobs_running.iter_mut().max_by_key(|r| r.started)
+        {
+            eprintln!(
+                "parallel: memory is low, requeueing job {}",
+                youngest.job.seq
+            );
+            youngest.terminate();
+            youngest.requeue = true;
+        }
+
+        // a stopped job would hold its slot forever, so it is reported and terminated; SIGCONT
+        // lets the SIGTERM through
+        for running in jobs_running.iter_mut() {
+            if !running.stopped
+                && let Some(signal) = running.stopped_by()
+            {
+                eprintln!(
+                    "parallel: job {} was stopped by signal {signal}, terminating it",
+                    running.job.seq
+                );
+                running.stopped = true;
+                running.terminate();
+                running.kill(libc::SIGCONT);
+            }
+        }
+
+        for running in jobs_running.iter_mut() {
+            if timeout.is_some_and(|timeout| running.started.elapsed() >= timeout) {
+                running.terminate();
             }
+            running.escalate();
         }
 
-        if let Some(maxload) = maxload {
-            loop {
-                if System::load_average().one < maxload {
-                    break;
+        // reap every job that has exited since the last pass, however many SIGCHLDs that took
+        let mut halting: Option<halt::When> = None;
+        let mut i = 0;
+        while i < jobs_running.len() {
+            match jobs_running[i].child.try_wait() {
+                Ok(Some(status)) => {
+                    let running = jobs_running.swap_remove(i);
+                    let runtime = running.started.elapsed();
+                    if let Some(joblog) = joblog.as_mut() {
+                        joblog.record(&running, runtime, status)?;
+                    }
+                    if let Some(results) = &results {
+                        results.record(&running, status)?;
+                    }
+                    let requeue = running.requeue;
+                    let mut job = running.job;
+                    if halted.is_some() {
+                        continue;
+                    }
+                    if requeue {
+                        queue.push_front(job);
+                        continue;
+                    }
+                    // exponential backoff: delay, 2*delay, 4*delay, ...; a retry too far off to
+                    // schedule is never coming, so the job fails straight away instead
+                    let backoff = options.retry_delay.saturating_mul(1 << job.tries.min(16));
+                    let retry_at = Instant::now().checked_add(backoff);
+                    if status.success() {
+                        runtimes.push(runtime);
+                        busy += runtime;
+                        succeeded += 1;
+                    } else if job.tries < options.retries
+                        && let Some(retry_at) = retry_at
+                    {
+                        job.tries += 1;
+                        job.not_before = Some(retry_at);
+                        queue.push_back(job);
+                        continue;
+                    } else {
+                        busy += runtime;
+                        failed += 1;
+                    }
+                    if let Some(halt) = options.halt
+                        && halt.triggered(succeeded, failed, total)
+                    {
+                        halted = Some(if halt.on_success {
+                            0
+                        } else {
+                            exit_value(status)
+                        });
+                        halting = Some(halt.when);
+                        queue.clear();
+                        blocks = None;
+                    }
+                }
+                Ok(None) => i += 1,
+                Err(_) => i += 1, // ignored, try_wait again later for this process
+            }
+        }
+        match halting {
+            _ if jobs_running.is_empty() => {}
+            Some(halt::When::Now) => {
+                eprintln!(
+                    "parallel: halting, killing {} running jobs",
+                    jobs_running.len()
+                );
+                for running in jobs_running.iter_mut() {
+                    running.terminate();
                 }
-                thread::sleep(time::Duration::from_millis(500));
             }
+            Some(halt::When::Soon) => eprintln!(
+                "parallel: halting, waiting for {} running jobs",
+                jobs_running.len()
+            ),
+            None => {}
         }
 
-        let child = Command::new(&job.command).args(&job.args).spawn()?;
-        jobs_running.push(child);
+        // fill the free slots; lowering the limit lets running jobs finish, it only holds off
+        // starting new ones
+        let mut held_until: Option<Instant> = None;
+        while jobs_running.len() < limit.jobs {
+            if queue.is_empty()
+                && let Some(source) = blocks.as_mut()
+            {
+                match source.try_next() {
+                    Ok(job) => {
+                        total += 1;
+                        queue.push_back(job);
+                    }
+                    Err(TryRecvError::Empty) => {}
+                    Err(TryRecvError::Disconnected) => blocks = None,
+                }
+            }
+            let Some(job) = queue.front() else {
+                break;
+            };
+            if let Some(not_before) = job.not_before
+                && not_before > Instant::now()
+            {
+                held_until = Some(not_before);
+                break;
+            }
+            if !resources.available(options) {
+                held_until = Some(Instant::now() + RESOURCE_POLL);
+                break;
+            }
+
+            let job = queue.pop_front().expect("front was a Some value");
+            let slot = (1..)
+                .find(|slot| jobs_running.iter().all(|running| running.slot != *slot))
+                .expect("fewer running jobs than slots");
+            let argv = job.argv(slot);
+            let mut command = Command::new(&argv[0]);
+            command
+                .args(&argv[1..])
+                .env("PARALLEL_SEQ", job.seq.to_string())
+                .env("PARALLEL_JOBSLOT", slot.to_string())
+                .process_group(0);
+            if let Some(results) = &results {
+                let (stdout, stderr) = results.start(job.seq, &argv)?;
+                command.stdout(stdout).stderr(stderr);
+            }
+            if job.block.is_some() {
+                command.stdin(Stdio::piped());
+            } else if tty {
+                // jobs run in process groups of their own, outside the terminal's foreground
+                // group, so reading it would only stop them
+                command.stdin(Stdio::null());
+            }
+            let mut child = command.spawn()?;
+            // fed from a thread of its own so a job that is slow to read never holds up the pool;
+            // one that exits without reading it all just ends the thread with EPIPE
+            if let Some(block) = job.block.clone() {
+                let mut stdin = child.stdin.take().expect("stdin was piped");
+                thread::spawn(move || stdin.write_all(&block));
+            }
+            jobs_running.push(Running {
+                child,
+                job,
+                argv,
+                slot,
+                start: SystemTime::now(),
+                started: Instant::now(),
+                terminated: None,
+                requeue: false,
+                stopped: false,
+            });
+        }
+
+        if let Some(progress) = progress.as_mut() {
+            progress.tick(&Counts {
+                done: succeeded + failed,
+                running: jobs_running.len(),
+                left: queue.len(),
+                busy,
+            });
+        }
+
+        if queue.is_empty() && jobs_running.is_empty() && blocks.is_none() {
+            break;
+        }
+
+        // a job that exits after the reaping above has already written to the socket, so this
+        // returns straight away rather than missing it; waiting on the socket rather than
+        // sleeping also keeps parallel responsive to signals
+        let poll = options.memfree_kill || limit.file.is_some();
+        let until = jobs_running
+            .iter()
+            .filter_map(|running| running.deadline(timeout))
+            .chain(progress.as_ref().map(Progress::next_draw))
+            .chain(poll.then(|| Instant::now() + RESOURCE_POLL))
+            .chain(held_until)
+            .min();
+        wait_for_signal(&mut wake, until)?;
+    }
+    for id in wake_ids {
+        signal_hook::low_level::unregister(id);
     }
-    for mut child in jobs_running {
-        exit_code |= child.wait()?.code().unwrap_or(1);
+    if let Some(progress) = progress.as_mut() {
+        progress.finish(&Counts {
+            done: succeeded + failed,
+            running: 0,
+            left: 0,
+            busy,
+        });
+    }
+    Ok(halted.unwrap_or(failed.min(101) as i32))
+}
+
+fn wait_for_signal(wake: &mut UnixStream, until: Option<Instant>) -> io::Result<()> {
+    let timeout = match until {
+        Some(until) => match until.checked_duration_since(Instant::now()) {
+            Some(timeout) if !timeout.is_zero() => Some(timeout),
+            _ => return Ok(()),
+        },
+        None => None,
+    };
+    wake.set_read_timeout(timeout)?;
+    let mut buf = [0; 64];
+    match wake.read(&mut buf) {
+        Ok(_) => Ok(()),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+        Err(e) => Err(e),
     }
-    Ok(exit_code)
 }
 
-fn split_args<I>(iter: I) -> (Vec<OsString>, Vec<OsString>)
+// accepts plain seconds or a number suffixed with s, m, h or d, e.g. "1.5", "30s", "2m"
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (number, unit) = match s.char_indices().last()? {
+        (i, 's') => (&s[..i], 1.0),
+        (i, 'm') => (&s[..i], 60.0),
+        (i, 'h') => (&s[..i], 3600.0),
+        (i, 'd') => (&s[..i], 86400.0),
+        _ => (s, 1.0),
+    };
+    let seconds = number.parse::<f64>().ok()? * unit;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+fn exit_value(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => status.signal().map_or_else(|| 1, |sig| 128 + sig),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Arguments {
+    // -- a b c, split into chunks of -n
+    List(Vec<OsString>),
+    // ::: a b ::: x y, as groups of lists linked together by :::+
+    Sources(Vec<Vec<Vec<OsString>>>),
+}
+
+fn split_args<I>(iter: I) -> (Vec<OsString>, Arguments)
 where
     I: Iterator<Item = OsString>,
 {
     let mut before: Vec<OsString> = Vec::new();
-    let mut after: Vec<OsString> = Vec::new();
+    let mut list: Vec<OsString> = Vec::new();
+    let mut groups: Vec<Vec<Vec<OsString>>> = Vec::new();
     let mut double_dash = false;
 
     for item in iter {
         if double_dash {
-            after.push(item);
-        } else if item == "--" {
+            list.push(item);
+        } else if groups.is_empty() && item == "--" {
             double_dash = true;
+        } else if item == ":::" || (groups.is_empty() && item == ":::+") {
+            groups.push(vec![Vec::new()]);
+        } else if item == ":::+" {
+            groups.last_mut().expect("not empty").push(Vec::new());
+        } else if let Some(group) = groups.last_mut() {
+            group
+                .last_mut()
+                .expect("groups start with a list")
+                .push(item);
         } else {
             before.push(item);
         }
     }
 
-    (before, after)
+    if groups.is_empty() {
+        (before, Arguments::List(list))
+    } else {
+        (before, Arguments::Sources(groups))
+    }
+}
+
+// every combination of one row from each group, the last group varying fastest; the lists
+// within a group are paired up element-wise, with shorter lists wrapping around
+fn combinations(groups: &[Vec<Vec<OsString>>]) -> Vec<Vec<OsString>> {
+    let mut combinations: Vec<Vec<OsString>> = vec![Vec::new()];
+    for lists in groups {
+        let rows = match lists.iter().any(Vec::is_empty) {
+            true => 0,
+            false => lists.iter().map(Vec::len).max().unwrap_or(0),
+        };
+        combinations = combinations
+            .iter()
+            .flat_map(|prefix| {
+                (0..rows).map(move |row| {
+                    let mut combination = prefix.clone();
+                    combination.extend(lists.iter().map(|list| list[row % list.len()].clone()));
+                    combination
+                })
+            })
+            .collect();
+    }
+    combinations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn os(words: &[&str]) -> Vec<OsString> {
+        words.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn argument_sources() {
+        let (fixed, arguments) = split_args(os(&["-v", "--", "a", ":::", "b"]).into_iter());
+        assert_eq!(os(&["-v"]), fixed);
+        assert_eq!(Arguments::List(os(&["a", ":::", "b"])), arguments);
+
+        let (fixed, arguments) = split_args(
+            os(&["{1}", ":::", "a", "b", ":::", "x", ":::+", "1", "2", "3"]).into_iter(),
+        );
+        assert_eq!(os(&["{1}"]), fixed);
+        let Arguments::Sources(groups) = arguments else {
+            panic!("expected sources");
+        };
+        assert_eq!(
+            vec![
+                os(&["a", "x", "1"]),
+                os(&["a", "x", "2"]),
+                os(&["a", "x", "3"]),
+                os(&["b", "x", "1"]),
+                os(&["b", "x", "2"]),
+                os(&["b", "x", "3"]),
+            ],
+            combinations(&groups)
+        );
+    }
+
+    #[test]
+    fn empty_source() {
+        let (_, arguments) = split_args(os(&[":::", "a", ":::"]).into_iter());
+        let Arguments::Sources(groups) = arguments else {
+            panic!("expected sources");
+        };
+        assert!(combinations(&groups).is_empty());
+    }
+
+    #[test]
+    fn quoting() {
+        let quote = |arg: &str| {
+            let mut out = Vec::new();
+            shell_quote(OsStr::new(arg), &mut out);
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!("dir/file.txt", quote("dir/file.txt"));
+        assert_eq!("''", quote(""));
+        assert_eq!("'two words'", quote("two words"));
+        assert_eq!("'it'\\''s'", quote("it's"));
+        assert_eq!("'a\nb'", quote("a\nb"));
+        assert_eq!("'$HOME'", quote("$HOME"));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(Some(Duration::from_secs(30)), parse_duration("30"));
+        assert_eq!(Some(Duration::from_millis(1500)), parse_duration("1.5s"));
+        assert_eq!(Some(Duration::from_secs(120)), parse_duration("2m"));
+        assert_eq!(Some(Duration::from_secs(3600)), parse_duration("1h"));
+        assert_eq!(Some(Duration::from_secs(86400)), parse_duration("1d"));
+        assert_eq!(None, parse_duration("-1"));
+        assert_eq!(None, parse_duration("soon"));
+        assert_eq!(None, parse_duration(""));
+    }
+
+    #[test]
+    fn timeouts() {
+        let ms = Duration::from_millis;
+        assert_eq!(Some(Timeout::Fixed(ms(2500))), Timeout::parse("2.5"));
+        assert_eq!(Some(Timeout::Median(2.0)), Timeout::parse("200%"));
+        assert_eq!(None, Timeout::parse("0%"));
+        assert_eq!(None, Timeout::parse("%"));
+        assert_eq!(None, Timeout::parse("inf%"));
+        assert_eq!(None, Timeout::parse("1e300%"));
+
+        let median = Timeout::Median(2.0);
+        assert_eq!(None, median.limit(&[ms(10), ms(20)]));
+        assert_eq!(Some(ms(40)), median.limit(&[ms(30), ms(10), ms(20)]));
+        assert_eq!(Some(ms(5)), Timeout::Fixed(ms(5)).limit(&[]));
+        let huge = [Duration::MAX, Duration::MAX, Duration::MAX];
+        assert_eq!(None, median.limit(&huge));
+    }
 }
@@ -0,0 +1,91 @@
+use std::io::{self, BufRead, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::thread;
+
+use super::Execution;
+
+// --pipe: stdin split into blocks that end at a newline, each fed to the stdin of its own job
+pub struct Blocks {
+    receiver: Receiver<Vec<u8>>,
+    template: Execution,
+    seq: usize,
+}
+
+impl Blocks {
+    // reads stdin on a thread of its own, which writes to wake once a block is ready and when
+    // stdin runs out
+    pub fn spawn(template: Execution, size: usize, wake: UnixStream) -> Blocks {
+        // one block waiting on top of the ones being fed to jobs
+        let (sender, receiver) = mpsc::sync_channel(1);
+        thread::spawn(move || read_blocks(size, sender, wake));
+        Blocks {
+            receiver,
+            template,
+            seq: 0,
+        }
+    }
+
+    // the job for the next block, Empty while it is still being read, Disconnected after the last
+    pub fn try_next(&mut self) -> Result<Execution, TryRecvError> {
+        let block = self.receiver.try_recv()?;
+        self.seq += 1;
+        Ok(Execution {
+            seq: self.seq,
+            command: self.template.command.clone(),
+            args: self.template.args.clone(),
+            inputs: Vec::new(),
+            interpolate: self.template.interpolate,
+            shell: self.template.shell.clone(),
+            block: Some(Arc::from(block)),
+            tries: 0,
+            not_before: None,
+        })
+    }
+}
+
+fn read_blocks(size: usize, sender: SyncSender<Vec<u8>>, mut wake: UnixStream) {
+    let mut stdin = io::stdin().lock();
+    loop {
+        match read_block(&mut stdin, size) {
+            Ok(block) if block.is_empty() => break,
+            Ok(block) => {
+                if sender.send(block).is_err() {
+                    return;
+                }
+                let _ = wake.write(&[0]);
+            }
+            Err(e) => {
+                eprintln!("parallel: stdin: {e}");
+                break;
+            }
+        }
+    }
+    drop(sender);
+    let _ = wake.write(&[0]);
+}
+
+// reads size bytes, then on to the end of the line they stop in
+fn read_block(input: &mut impl BufRead, size: usize) -> io::Result<Vec<u8>> {
+    let mut block = Vec::new();
+    input.take(size as u64).read_to_end(&mut block)?;
+    if block.len() == size && block.last() != Some(&b'\n') {
+        input.read_until(b'\n', &mut block)?;
+    }
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks() {
+        let mut input = io::Cursor::new(b"one\ntwo\nthree\nfour".to_vec());
+        assert_eq!(b"one\n", &read_block(&mut input, 4).unwrap()[..]);
+        assert_eq!(b"two\nthree\n", &read_block(&mut input, 5).unwrap()[..]);
+        assert_eq!(b"four", &read_block(&mut input, 100).unwrap()[..]);
+        assert!(read_block(&mut input, 100).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum When {
+    // stop starting new jobs and wait for the running ones
+    Soon,
+    // kill the running jobs as well
+    Now,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Threshold {
+    Count(usize),
+    Percent(f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Halt {
+    pub when: When,
+    pub on_success: bool,
+    pub threshold: Threshold,
+}
+
+impl Halt {
+    // accepts "soon,fail=3", "now,fail=20%", "now,success=1" and the legacy "1" and "2"
+    pub fn parse(s: &str) -> Option<Halt> {
+        let (when, condition) = match s {
+            "1" => return Some(Halt::fail_once(When::Soon)),
+            "2" => return Some(Halt::fail_once(When::Now)),
+            _ => s.split_once(',')?,
+        };
+        let when = match when {
+            "soon" => When::Soon,
+            "now" => When::Now,
+            _ => return None,
+        };
+        let (on_success, threshold) = match condition.split_once('=')? {
+            ("fail", threshold) => (false, threshold),
+            ("success", threshold) => (true, threshold),
+            _ => return None,
+        };
+        let threshold = match threshold.strip_suffix('%') {
+            Some(pct) => Threshold::Percent(pct.parse::<f64>().ok().filter(|p| *p > 0.0)?),
+            None => Threshold::Count(threshold.parse::<usize>().ok().filter(|n| *n > 0)?),
+        };
+        Some(Halt {
+            when,
+            on_success,
+            threshold,
+        })
+    }
+
+    fn fail_once(when: When) -> Halt {
+        Halt {
+            when,
+            on_success: false,
+            threshold: Threshold::Count(1),
+        }
+    }
+
+    pub fn triggered(&self, succeeded: usize, failed: usize, total: usize) -> bool {
+        let count = if self.on_success { succeeded } else { failed };
+        match self.threshold {
+            Threshold::Count(n) => count >= n,
+            Threshold::Percent(pct) => count > 0 && count as f64 * 100.0 >= pct * total as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Some(Halt {
+                when: When::Soon,
+                on_success: false,
+                threshold: Threshold::Count(1)
+            }),
+            Halt::parse("soon,fail=1")
+        );
+        assert_eq!(
+            Some(Halt {
+                when: When::Now,
+                on_success: false,
+                threshold: Threshold::Percent(20.0)
+            }),
+            Halt::parse("now,fail=20%")
+        );
+        assert_eq!(
+            Some(Halt {
+                when: When::Now,
+                on_success: true,
+                threshold: Threshold::Count(1)
+            }),
+            Halt::parse("now,success=1")
+        );
+        assert_eq!(Halt::parse("now,fail=1"), Halt::parse("2"));
+        assert_eq!(None, Halt::parse("later,fail=1"));
+        assert_eq!(None, Halt::parse("now,fail=0"));
+        assert_eq!(None, Halt::parse("now,done=1"));
+        assert_eq!(None, Halt::parse("now"));
+    }
+
+    #[test]
+    fn triggered() {
+        let halt = Halt::parse("now,fail=20%").unwrap();
+        assert!(!halt.triggered(9, 1, 10));
+        assert!(halt.triggered(0, 2, 10));
+        let halt = Halt::parse("soon,success=2").unwrap();
+        assert!(!halt.triggered(1, 5, 10));
+        assert!(halt.triggered(2, 0, 10));
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::{Running, command_line};
+
+const HEADER: &str = "Seq\tStarttime\tJobRuntime\tExitval\tSignal\tCommand\n";
+
+pub struct JobLog {
+    file: File,
+}
+
+impl JobLog {
+    pub fn open(path: &Path, append: bool) -> io::Result<JobLog> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(HEADER.as_bytes())?;
+        }
+        Ok(JobLog { file })
+    }
+
+    pub fn record(
+        &mut self,
+        running: &Running,
+        runtime: Duration,
+        status: ExitStatus,
+    ) -> io::Result<()> {
+        let start = running.start.duration_since(UNIX_EPOCH).unwrap_or_default();
+        // one write per line so an interrupted run never leaves a partial record
+        let line = format!(
+            "{}\t{}.{:03}\t{}.{:03}\t{}\t{}\t{}\n",
+            running.job.seq,
+            start.as_secs(),
+            start.subsec_millis(),
+            runtime.as_secs(),
+            runtime.subsec_millis(),
+            status.code().unwrap_or(0),
+            status.signal().unwrap_or(0),
+            command_line(&running.argv).replace(['\t', '\n'], " "),
+        );
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+// maps each sequence number to whether its last recorded run succeeded
+pub fn read_outcomes(path: &Path) -> io::Result<HashMap<usize, bool>> {
+    let mut outcomes = HashMap::new();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(outcomes),
+        Err(e) => return Err(e),
+    };
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.splitn(6, '\t').collect();
+        let (Some(seq), Some(exitval), Some(signal)) = (
+            fields.first().and_then(|f| f.parse::<usize>().ok()),
+            fields.get(3).and_then(|f| f.parse::<i32>().ok()),
+            fields.get(4).and_then(|f| f.parse::<i32>().ok()),
+        ) else {
+            continue; // header or truncated line
+        };
+        outcomes.insert(seq, exitval == 0 && signal == 0);
+    }
+    Ok(outcomes)
+}
@@ -0,0 +1,114 @@
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+// redraw at most this often on a terminal, and at least once a second
+const TTY_REDRAW: Duration = Duration::from_millis(100);
+const TTY_REFRESH: Duration = Duration::from_secs(1);
+// otherwise print a plain line this often
+const PLAIN_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct Progress {
+    tty: bool,
+    eta: bool,
+    started: Instant,
+    drawn: Option<Instant>,
+}
+
+pub struct Counts {
+    pub done: usize,
+    pub running: usize,
+    pub left: usize,
+    // summed runtime of the finished jobs
+    pub busy: Duration,
+}
+
+impl Progress {
+    pub fn new(eta: bool) -> Progress {
+        Progress {
+            tty: io::stderr().is_terminal(),
+            eta,
+            started: Instant::now(),
+            drawn: None,
+        }
+    }
+
+    pub fn next_draw(&self) -> Instant {
+        let interval = if self.tty {
+            TTY_REFRESH
+        } else {
+            PLAIN_INTERVAL
+        };
+        self.drawn.unwrap_or(self.started) + interval
+    }
+
+    pub fn tick(&mut self, counts: &Counts) {
+        let due = match self.drawn {
+            None => true,
+            Some(_) if Instant::now() >= self.next_draw() => true,
+            Some(drawn) => self.tty && drawn.elapsed() >= TTY_REDRAW,
+        };
+        if due {
+            self.draw(counts);
+        }
+    }
+
+    pub fn finish(&mut self, counts: &Counts) {
+        self.draw(counts);
+        if self.tty {
+            eprintln!();
+        }
+    }
+
+    fn draw(&mut self, counts: &Counts) {
+        let mut line = format!(
+            "parallel: {} done, {} running, {} left",
+            counts.done, counts.running, counts.left
+        );
+        if counts.done > 0 {
+            let avg = counts.busy / counts.done as u32;
+            line.push_str(&format!(", avg {}", format_duration(avg)));
+        }
+        if self.eta {
+            let eta = match counts.done {
+                0 => String::from("?"),
+                done => {
+                    let pending = (counts.running + counts.left) as f64;
+                    format_duration(self.started.elapsed().mul_f64(pending / done as f64))
+                }
+            };
+            line.push_str(&format!(", eta {eta}"));
+        }
+        let mut stderr = io::stderr().lock();
+        let _ = if self.tty {
+            write!(stderr, "\r\x1b[K{line}")
+        } else {
+            writeln!(stderr, "{line}")
+        };
+        let _ = stderr.flush();
+        self.drawn = Some(Instant::now());
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs < 60 {
+        format!("{:.1}s", d.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!("0.2s", format_duration(Duration::from_millis(240)));
+        assert_eq!("59.0s", format_duration(Duration::from_secs(59)));
+        assert_eq!("1m40s", format_duration(Duration::from_secs(100)));
+        assert_eq!("2h03m", format_duration(Duration::from_secs(7380)));
+    }
+}
@@ -0,0 +1,43 @@
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+
+use super::{Running, exit_value, quote_command_line};
+
+// keeps each job's command line, output and exit value in DIR/<seq>/
+pub struct Results {
+    dir: PathBuf,
+}
+
+impl Results {
+    pub fn create(dir: &Path) -> io::Result<Results> {
+        fs::create_dir_all(dir)?;
+        Ok(Results {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    // records the command line of a job about to start and returns its stdout and stderr; a
+    // retried job starts over, and has no exitval until it finishes again
+    pub fn start(&self, seq: usize, argv: &[OsString]) -> io::Result<(Stdio, Stdio)> {
+        let dir = self.dir.join(seq.to_string());
+        fs::create_dir_all(&dir)?;
+        match fs::remove_file(dir.join("exitval")) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let mut command = quote_command_line(argv);
+        command.push(b'\n');
+        fs::write(dir.join("command"), command)?;
+        let stdout = File::create(dir.join("stdout"))?;
+        let stderr = File::create(dir.join("stderr"))?;
+        Ok((stdout.into(), stderr.into()))
+    }
+
+    pub fn record(&self, running: &Running, status: ExitStatus) -> io::Result<()> {
+        let dir = self.dir.join(running.job.seq.to_string());
+        fs::write(dir.join("exitval"), format!("{}\n", exit_value(status)))
+    }
+}
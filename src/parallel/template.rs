@@ -0,0 +1,293 @@
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+use super::shell_quote;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transform {
+    // {}
+    Whole,
+    // {.}
+    NoExtension,
+    // {/}
+    Basename,
+    // {//}
+    Dirname,
+    // {/.}
+    BasenameNoExtension,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Replacement {
+    // {} and friends apply to each input in turn, {1} and friends to the numbered one
+    Input(Option<usize>, Transform),
+    // {#}
+    Seq,
+    // {%}
+    Slot,
+}
+
+const TRANSFORMS: &[(&[u8], Transform)] = &[
+    (b"}", Transform::Whole),
+    (b".}", Transform::NoExtension),
+    (b"/}", Transform::Basename),
+    (b"//}", Transform::Dirname),
+    (b"/.}", Transform::BasenameNoExtension),
+];
+
+pub struct Context<'a> {
+    pub inputs: &'a [OsString],
+    pub seq: usize,
+    pub slot: usize,
+    // shell-quote substituted inputs, for commands that run through a shell
+    pub quote: bool,
+}
+
+enum Piece<'a> {
+    Literal(&'a [u8]),
+    Replace(Replacement),
+}
+
+// recognises a replacement string at the start of s, returning it with its length
+fn replacement_at(s: &[u8]) -> Option<(Replacement, usize)> {
+    let rest = s.strip_prefix(b"{")?;
+    if rest.starts_with(b"#}") {
+        return Some((Replacement::Seq, 3));
+    }
+    if rest.starts_with(b"%}") {
+        return Some((Replacement::Slot, 3));
+    }
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    let position = match digits {
+        0 => None,
+        _ => Some(
+            std::str::from_utf8(&rest[..digits])
+                .ok()?
+                .parse::<usize>()
+                .ok()
+                .filter(|n| *n > 0)?,
+        ),
+    };
+    TRANSFORMS
+        .iter()
+        .find(|(suffix, _)| rest[digits..].starts_with(suffix))
+        .map(|(suffix, transform)| {
+            (
+                Replacement::Input(position, *transform),
+                1 + digits + suffix.len(),
+            )
+        })
+}
+
+fn parse(word: &[u8]) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut literal = 0;
+    let mut i = 0;
+    while i < word.len() {
+        match replacement_at(&word[i..]) {
+            Some((replacement, len)) => {
+                if literal < i {
+                    pieces.push(Piece::Literal(&word[literal..i]));
+                }
+                pieces.push(Piece::Replace(replacement));
+                i += len;
+                literal = i;
+            }
+            None => i += 1,
+        }
+    }
+    if literal < word.len() {
+        pieces.push(Piece::Literal(&word[literal..]));
+    }
+    pieces
+}
+
+// expands every replacement string in words; a word that refers to {} is repeated once for
+// each input when a job has more than one
+pub fn expand(words: &[OsString], ctx: &Context) -> Vec<OsString> {
+    let mut expanded = Vec::with_capacity(words.len());
+    for word in words {
+        let pieces = parse(word.as_bytes());
+        let uses_each_input = pieces
+            .iter()
+            .any(|piece| matches!(piece, Piece::Replace(Replacement::Input(None, _))));
+        if uses_each_input && ctx.inputs.len() > 1 {
+            for input in ctx.inputs {
+                expanded.push(render(&pieces, input.as_bytes(), ctx));
+            }
+        } else {
+            let input = ctx.inputs.first().map_or(&b""[..], |i| i.as_bytes());
+            expanded.push(render(&pieces, input, ctx));
+        }
+    }
+    expanded
+}
+
+fn render(pieces: &[Piece], input: &[u8], ctx: &Context) -> OsString {
+    let mut out = Vec::new();
+    for piece in pieces {
+        match piece {
+            Piece::Literal(literal) => out.extend_from_slice(literal),
+            Piece::Replace(Replacement::Input(position, transform)) => {
+                let input = match position {
+                    None => input,
+                    Some(n) => ctx.inputs.get(n - 1).map_or(&b""[..], |i| i.as_bytes()),
+                };
+                let value = match transform {
+                    Transform::Whole => input,
+                    Transform::NoExtension => no_extension(input),
+                    Transform::Basename => basename(input),
+                    Transform::Dirname => dirname(input),
+                    Transform::BasenameNoExtension => no_extension(basename(input)),
+                };
+                if ctx.quote {
+                    shell_quote(OsStr::from_bytes(value), &mut out);
+                } else {
+                    out.extend_from_slice(value);
+                }
+            }
+            Piece::Replace(Replacement::Seq) => {
+                out.extend_from_slice(ctx.seq.to_string().as_bytes())
+            }
+            Piece::Replace(Replacement::Slot) => {
+                out.extend_from_slice(ctx.slot.to_string().as_bytes())
+            }
+        }
+    }
+    OsString::from_vec(out)
+}
+
+// strips the last .ext from the final path component, like GNU parallel's s:\.[^/.]*$::
+fn no_extension(path: &[u8]) -> &[u8] {
+    match path.iter().rposition(|b| *b == b'.' || *b == b'/') {
+        Some(i) if path[i] == b'.' => &path[..i],
+        _ => path,
+    }
+}
+
+fn basename(path: &[u8]) -> &[u8] {
+    match path.iter().rposition(|b| *b == b'/') {
+        Some(i) => &path[i + 1..],
+        None => path,
+    }
+}
+
+fn dirname(path: &[u8]) -> &[u8] {
+    let trimmed = match path.iter().rposition(|b| *b != b'/') {
+        Some(end) => &path[..=end],
+        None if path.is_empty() => return b".",
+        None => return b"/",
+    };
+    match trimmed.iter().rposition(|b| *b == b'/') {
+        None => b".",
+        Some(i) => match trimmed[..i].iter().rposition(|b| *b != b'/') {
+            Some(end) => &trimmed[..=end],
+            None => b"/",
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn os(words: &[&str]) -> Vec<OsString> {
+        words.iter().map(OsString::from).collect()
+    }
+
+    fn expand_one(word: &str, input: &str) -> String {
+        let inputs = os(&[input]);
+        let ctx = Context {
+            inputs: &inputs,
+            seq: 7,
+            slot: 2,
+            quote: false,
+        };
+        expand(&os(&[word]), &ctx)[0].to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn replacements() {
+        let input = "dir/sub.d/photo.tar.jpg";
+        assert_eq!("dir/sub.d/photo.tar.jpg", expand_one("{}", input));
+        assert_eq!("dir/sub.d/photo.tar", expand_one("{.}", input));
+        assert_eq!("photo.tar.jpg", expand_one("{/}", input));
+        assert_eq!("dir/sub.d", expand_one("{//}", input));
+        assert_eq!("photo.tar", expand_one("{/.}", input));
+        assert_eq!("7-2", expand_one("{#}-{%}", input));
+        assert_eq!("dir/sub.d/photo.tar.png", expand_one("{.}.png", input));
+        assert_eq!("{x}{", expand_one("{x}{", input));
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(b"a/b", no_extension(b"a/b"));
+        assert_eq!(b"a.d/b", no_extension(b"a.d/b"));
+        assert_eq!(b"", no_extension(b".bashrc"));
+        assert_eq!(b"", basename(b"dir/"));
+        assert_eq!(b".", dirname(b"file"));
+        assert_eq!(b"/", dirname(b"/file"));
+        assert_eq!(b"a", dirname(b"a//b/"));
+        assert_eq!(b"/", dirname(b"//"));
+    }
+
+    #[test]
+    fn positional() {
+        let inputs = os(&["a/x.c", "b"]);
+        let ctx = Context {
+            inputs: &inputs,
+            seq: 1,
+            slot: 1,
+            quote: false,
+        };
+        assert_eq!(
+            os(&["b-a/x.c", "x", "", "{0}"]),
+            expand(&os(&["{2}-{1}", "{1/.}", "{3}", "{0}"]), &ctx)
+        );
+    }
+
+    #[test]
+    fn multiple_inputs() {
+        let inputs = os(&["a.c", "b.c"]);
+        let ctx = Context {
+            inputs: &inputs,
+            seq: 1,
+            slot: 1,
+            quote: false,
+        };
+        assert_eq!(
+            os(&["cc", "-o", "job1", "a.c", "b.c"]),
+            expand(&os(&["cc", "-o", "job{#}", "{}"]), &ctx)
+        );
+    }
+
+    #[test]
+    fn non_utf8() {
+        let inputs = vec![OsString::from_vec(b"caf\xe9.txt".to_vec())];
+        let ctx = Context {
+            inputs: &inputs,
+            seq: 1,
+            slot: 1,
+            quote: false,
+        };
+        assert_eq!(
+            vec![OsString::from_vec(b"caf\xe9".to_vec())],
+            expand(&os(&["{.}"]), &ctx)
+        );
+    }
+
+    #[test]
+    fn quoted() {
+        let inputs = os(&["it's a file.txt", "plain"]);
+        let ctx = Context {
+            inputs: &inputs,
+            seq: 3,
+            slot: 1,
+            quote: true,
+        };
+        assert_eq!(
+            os(&["wc", "'it'\\''s a file'.out", "plain", ">", "3-3"]),
+            expand(&os(&["wc", "{1.}.out", "{2}", ">", "{#}-{#}"]), &ctx)
+        );
+    }
+}
@@ -1,18 +1,82 @@
-use signal_hook::{consts::SIGPIPE, iterator::Signals};
+mod dispatch;
+mod feed;
+mod output;
+mod target;
+
+use signal_hook::consts::SIGPIPE;
 use std::{
-    ffi::OsString,
+    ffi::{OsStr, OsString},
     io::{self, BufRead, Write},
-    process::{self, Child, Command, Stdio},
-    thread,
+    os::unix::{
+        ffi::OsStrExt,
+        process::{CommandExt, ExitStatusExt},
+    },
+    process::{self, Child, Command, ExitStatus, Stdio},
+    sync::Arc,
+    thread::JoinHandle,
 };
 
+use crate::common::parse_size;
+use dispatch::{Dispatch, Rule};
+use feed::{Feed, Progress};
+use regex::bytes::Regex;
+use target::Target;
+
 fn usage() {
-    eprintln!(r#"Usage: pee [--[no-]ignore-sigpipe] [--[no-]ignore-write-errors] ["command"...]"#);
+    eprintln!(r#"Usage: pee [--[no-]ignore-sigpipe] [--[no-]ignore-write-errors]"#);
+    eprintln!(r#"           [--max-lag=SIZE] [--on-lag=block|drop|kill] [--serialize] [--prefix]"#);
+    eprintln!(
+        r#"           [--status=first|worst|mask] [--route=REGEX=command...] [--default=command]"#
+    );
+    eprintln!(r#"           [--balance=round-robin|hash:FIELD]"#);
+    eprintln!(r#"           ["command" | ">file" | ">>file" | "fifo:path"...]"#);
+}
+
+// --balance: each line goes to just one of the commands
+#[derive(Clone, Copy)]
+enum Balance {
+    RoundRobin,
+    // the 1-based whitespace-separated field to hash
+    Hash(usize),
+}
+
+// what to do with a command that falls more than --max-lag bytes behind; for drop and kill,
+// that is behind the least-lagged command, which pee waits on rather than outrun
+#[derive(Clone, Copy, PartialEq)]
+enum Lag {
+    // hold up reading stdin, and so every other command, until it catches up
+    Block,
+    // skip input for it until it catches up
+    Drop,
+    // kill it and carry on without it
+    Kill,
+}
+
+// how the commands' exit statuses combine into pee's own
+#[derive(Clone, Copy)]
+enum Status {
+    // the status of the first command, in the order given, that failed
+    First,
+    // the highest status of any command
+    Worst,
+    // bit N-1 set if the Nth command failed; bit 7 covers the 8th command and every later one
+    Mask,
 }
 
 struct Args {
     ignore_sigpipe: bool,
     ignore_write_errors: bool,
+    max_lag: usize,
+    on_lag: Lag,
+    status: Status,
+    // write each command's output in one piece, in command order
+    serialize: bool,
+    // tag each line of output with the command's number, or the label of a label=command
+    prefix: bool,
+    // each line goes to the first of these whose pattern it matches, or else to default
+    routes: Vec<(Regex, OsString)>,
+    default: Option<OsString>,
+    balance: Option<Balance>,
     commands: Vec<OsString>,
 }
 
@@ -20,6 +84,14 @@ fn parse_args() -> Result<Args, lexopt::Error> {
     use lexopt::prelude::*;
     let mut ignore_sigpipe = true;
     let mut ignore_write_errors = true;
+    let mut max_lag = 1024 * 1024;
+    let mut on_lag = Lag::Block;
+    let mut status = Status::First;
+    let mut serialize = false;
+    let mut prefix = false;
+    let mut routes = Vec::new();
+    let mut default = None;
+    let mut balance = None;
     let mut commands: Vec<OsString> = Vec::new();
     let mut parser = lexopt::Parser::from_env();
     while let Some(arg) = parser.next()? {
@@ -28,17 +100,81 @@ fn parse_args() -> Result<Args, lexopt::Error> {
             Long("no-ignore-sigpipe") => ignore_sigpipe = false,
             Long("ignore-write-errors") => ignore_write_errors = true,
             Long("no-ignore-write-errors") => ignore_write_errors = false,
+            Long("max-lag") => {
+                max_lag = parser
+                    .value()?
+                    .to_str()
+                    .and_then(parse_size)
+                    .and_then(|size| usize::try_from(size).ok())
+                    .ok_or("--max-lag requires a size such as 64K or 10M")?
+            }
+            Long("on-lag") => {
+                on_lag = match parser.value()?.to_str() {
+                    Some("block") => Lag::Block,
+                    Some("drop") => Lag::Drop,
+                    Some("kill") => Lag::Kill,
+                    _ => return Err("--on-lag must be one of block, drop or kill".into()),
+                }
+            }
+            Long("status") => {
+                status = match parser.value()?.to_str() {
+                    Some("first") => Status::First,
+                    Some("worst") => Status::Worst,
+                    Some("mask") => Status::Mask,
+                    _ => return Err("--status must be one of first, worst or mask".into()),
+                }
+            }
+            Long("serialize" | "ordered") => serialize = true,
+            Long("prefix") => prefix = true,
+            Long("route") => {
+                let route = parser.value()?;
+                // the first = ends the pattern, so one in it has to be written as \x3d
+                let (pattern, command) = route
+                    .to_str()
+                    .and_then(|route| route.split_once('='))
+                    .ok_or("--route requires REGEX=command")?;
+                let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+                routes.push((pattern, OsString::from(command)));
+            }
+            Long("default") => default = Some(parser.value()?),
+            Long("balance") => {
+                let value = parser.value()?;
+                balance = Some(match value.to_str() {
+                    Some("round-robin") => Balance::RoundRobin,
+                    Some(hash) => hash
+                        .strip_prefix("hash:")
+                        .and_then(|field| field.parse::<usize>().ok())
+                        .filter(|field| *field > 0)
+                        .map(Balance::Hash)
+                        .ok_or("--balance must be round-robin or hash:FIELD")?,
+                    None => return Err("--balance must be round-robin or hash:FIELD".into()),
+                })
+            }
             Value(val) => commands.push(val),
             _ => return Err(arg.unexpected()),
         }
     }
-    if commands.is_empty() {
+    if default.is_some() && routes.is_empty() {
+        return Err("--default requires --route".into());
+    }
+    if balance.is_some() && !routes.is_empty() {
+        return Err("--balance cannot be combined with --route".into());
+    }
+    if commands.is_empty() && routes.is_empty() {
         return Err(lexopt::Error::from("expected COMMAND"));
     }
 
     Ok(Args {
         ignore_sigpipe,
         ignore_write_errors,
+        max_lag,
+        on_lag,
+        status,
+        serialize,
+        prefix,
+        routes,
+        default,
+        balance,
         commands,
     })
 }
@@ -50,71 +186,293 @@ pub fn pee() -> io::Result<()> {
         process::exit(1);
     });
 
-    if !args.ignore_sigpipe {
-        let mut signals = Signals::new([SIGPIPE])?;
-        thread::Builder::new()
-            .name(String::from("SIGPIPE-handler"))
-            .spawn(move || {
-                if let Some(sig) = signals.forever().next() {
-                    process::exit(128 + sig);
-                }
-            })?;
+    // the commands given as arguments get all of the input unless balanced, routed ones only
+    // their lines
+    let mut commands = args.commands;
+    let mut broadcast = commands.len();
+    let mut dispatch = None;
+    if let Some(balance) = args.balance {
+        let workers = (0..commands.len()).collect();
+        dispatch = Some(Dispatch::new(match balance {
+            Balance::RoundRobin => Rule::RoundRobin { workers, next: 0 },
+            Balance::Hash(field) => Rule::Hash { field, workers },
+        }));
+        broadcast = 0;
+    } else if !args.routes.is_empty() {
+        let mut add = |command| {
+            commands.push(command);
+            commands.len() - 1
+        };
+        let routes = args
+            .routes
+            .into_iter()
+            .map(|(pattern, command)| (pattern, add(command)))
+            .collect();
+        let default = args.default.map(add);
+        dispatch = Some(Dispatch::new(Rule::Route { routes, default }));
     }
 
-    let mut children: Vec<Child> = args
-        .commands
+    let capture = args.serialize || args.prefix;
+    let progress = Arc::new(Progress::default());
+    let mut consumers: Vec<Consumer> = commands
         .into_iter()
-        .map(|command| {
-            Command::new("/bin/sh")
+        .enumerate()
+        .map(|(i, mut command)| {
+            let mut tag = None;
+            if args.prefix {
+                let mut prefix = (i + 1).to_string().into_bytes();
+                if let Some((label, rest)) = output::label(command.as_bytes()) {
+                    prefix = label.to_vec();
+                    command = OsStr::from_bytes(rest).to_os_string();
+                }
+                prefix.extend_from_slice(b": ");
+                tag = Some(prefix);
+            }
+            let target = match Target::parse(&command) {
+                Target::Command(command) => command,
+                target => {
+                    return Consumer {
+                        command,
+                        child: None,
+                        feed: Feed::spawn(move || target.open(), &progress),
+                        output: None,
+                        routed: i >= broadcast,
+                        live: true,
+                        dropped: false,
+                    };
+                }
+            };
+            let mut shell = Command::new("/bin/sh");
+            shell
                 .arg("-c")
-                .arg(&command)
+                .arg(&target)
                 .stdin(Stdio::piped())
                 .stderr(Stdio::inherit())
-                .stdout(Stdio::inherit())
+                .stdout(if capture {
+                    Stdio::piped()
+                } else {
+                    Stdio::inherit()
+                });
+            // --on-lag=kill has to reach everything the shell starts, so each command then leads a
+            // process group of its own; otherwise they stay in the terminal's foreground group
+            if args.on_lag == Lag::Kill {
+                shell.process_group(0);
+            }
+            let mut child = shell
                 .spawn()
-                .unwrap_or_else(|_| panic!("failed to spawn \"{command:?}\""))
+                .unwrap_or_else(|_| panic!("failed to spawn \"{command:?}\""));
+            let stdin = child.stdin.take().expect("stdin was piped");
+            let feed = Feed::spawn(move || Ok(stdin), &progress);
+            let output = child
+                .stdout
+                .take()
+                .map(|stdout| output::collect(stdout, tag, args.serialize));
+            Consumer {
+                command,
+                child: Some(child),
+                feed,
+                output,
+                routed: i >= broadcast,
+                live: true,
+                dropped: false,
+            }
         })
         .collect();
 
     let stdin = io::stdin();
     let mut stdin = stdin.lock();
+    let mut failures = Failures::default();
+    let mut batches = vec![Vec::new(); consumers.len()];
     loop {
         let buffer = stdin.fill_buf()?;
-        let buflen = buffer.len();
-        if buflen == 0 {
-            break;
+        let eof = buffer.is_empty();
+        let chunk: Arc<[u8]> = Arc::from(buffer);
+        stdin.consume(chunk.len());
+        match dispatch.as_mut() {
+            Some(dispatch) if eof => dispatch.finish(&mut batches),
+            Some(dispatch) => dispatch.split(&chunk, &mut batches),
+            None => {}
         }
-        let mut peed = false;
-        for child in &mut children {
-            if let Some(write_result) = child.stdin.as_ref().map(|mut s| s.write_all(buffer)) {
-                if write_result.is_err() && !args.ignore_write_errors {
-                    exit_children(&mut children, true)?;
-                    process::exit(1);
+        let sends: Vec<Option<Arc<[u8]>>> = consumers
+            .iter()
+            .zip(&mut batches)
+            .map(|(consumer, batch)| {
+                let data = match consumer.routed {
+                    true => Arc::from(std::mem::take(batch)),
+                    false => Arc::clone(&chunk),
+                };
+                (consumer.live && !data.is_empty()).then_some(data)
+            })
+            .collect();
+        // under drop and kill, lag is measured from the least-lagged of the commands getting
+        // input this time, and stdin is left unread until that one has room
+        let least = (args.on_lag != Lag::Block).then(|| {
+            let least = || {
+                consumers
+                    .iter()
+                    .zip(&sends)
+                    .filter(|(_, data)| data.is_some())
+                    .map(|(consumer, _)| consumer.feed.backlog())
+                    .min()
+            };
+            progress.wait_until(|| least().is_none_or(|backlog| backlog < args.max_lag));
+            least().unwrap_or(0)
+        });
+        for (i, (consumer, data)) in consumers.iter_mut().zip(sends).enumerate() {
+            let Some(data) = data else {
+                continue;
+            };
+            let sent = match least {
+                None => consumer.feed.send(&data, args.max_lag),
+                Some(least) if consumer.feed.backlog() >= least + args.max_lag => {
+                    if args.on_lag == Lag::Kill {
+                        eprintln!(
+                            "pee: {}: fell behind, killing it",
+                            consumer.command.to_string_lossy()
+                        );
+                        if let Some(child) = &consumer.child {
+                            unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                        }
+                        consumer.feed.abandon();
+                        consumer.live = false;
+                        if let Some(dispatch) = dispatch.as_mut() {
+                            dispatch.retire(i);
+                        }
+                    } else if !consumer.dropped {
+                        eprintln!(
+                            "pee: {}: fell behind, dropping input",
+                            consumer.command.to_string_lossy()
+                        );
+                        consumer.dropped = true;
+                    }
+                    continue;
                 }
-                if write_result.is_ok() {
-                    peed = true;
+                Some(_) => consumer.feed.queue(&data),
+            };
+            if let Err(e) = sent {
+                failures.report(&consumer.command, &e);
+                consumer.live = false;
+                if let Some(dispatch) = dispatch.as_mut() {
+                    dispatch.retire(i);
                 }
             }
         }
-        stdin.consume(buflen);
-        if !peed {
+        // there is no point reading on once nothing is left to feed
+        if eof || !consumers.iter().any(|c| c.live) {
             break;
         }
     }
 
-    exit_children(&mut children, false)?;
+    for consumer in &consumers {
+        consumer.feed.close();
+    }
+    for consumer in consumers.iter_mut().filter(|c| c.live) {
+        if let Some(e) = consumer.feed.join() {
+            failures.report(&consumer.command, &e);
+        }
+    }
+    // in command order, each as soon as it and the ones before it are done
+    for consumer in &mut consumers {
+        if let Some(output) = consumer.output.take() {
+            let kept = output.join().unwrap_or_default();
+            let _ = io::stdout().lock().write_all(&kept);
+        }
+    }
+    let statuses = exit_children(&mut consumers)?;
+    if failures.write_error && !args.ignore_write_errors {
+        process::exit(1);
+    }
+    if failures.closed_early && !args.ignore_sigpipe {
+        process::exit(128 + SIGPIPE);
+    }
+    match combine(args.status, &statuses) {
+        0 => Ok(()),
+        code => process::exit(code),
+    }
+}
+
+struct Consumer {
+    // as given, for messages
+    command: OsString,
+    // None for files and fifos
+    child: Option<Child>,
+    feed: Feed,
+    // collects its stdout for --serialize and --prefix
+    output: Option<JoinHandle<Vec<u8>>>,
+    // gets only the lines dispatched to it
+    routed: bool,
+    // still being fed
+    live: bool,
+    // has had input dropped for falling behind
+    dropped: bool,
+}
 
-    Ok(())
+// a command that stops taking input is reported and dropped while the others carry on; the
+// --ignore options only decide whether pee's exit status reflects it afterwards
+#[derive(Default)]
+struct Failures {
+    // a command exited, or closed its stdin, before reading all of its input
+    closed_early: bool,
+    // any failed write, an early close included, so either flag can make it fail pee
+    write_error: bool,
 }
 
-fn exit_children(children: &mut Vec<Child>, kill: bool) -> io::Result<()> {
-    if kill {
-        for child in &mut *children {
-            let _ = child.kill();
+impl Failures {
+    fn report(&mut self, command: &OsStr, e: &io::Error) {
+        if e.kind() == io::ErrorKind::BrokenPipe {
+            eprintln!("pee: {}: closed its input early", command.to_string_lossy());
+            self.closed_early = true;
+        } else {
+            eprintln!("pee: {}: {e}", command.to_string_lossy());
         }
+        self.write_error = true;
     }
-    for child in children {
-        let _ = child.wait();
+}
+
+// waits for every child, returning their exit values with signals mapped to 128+N
+fn exit_children(consumers: &mut [Consumer]) -> io::Result<Vec<i32>> {
+    // files and fifos count as succeeding, their write errors are handled as they happen
+    Ok(consumers
+        .iter_mut()
+        .map(|c| {
+            c.child
+                .as_mut()
+                .map_or(0, |child| child.wait().map_or(1, exit_value))
+        })
+        .collect())
+}
+
+fn exit_value(status: ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => status.signal().map_or(1, |sig| 128 + sig),
+    }
+}
+
+fn combine(policy: Status, statuses: &[i32]) -> i32 {
+    match policy {
+        Status::First => statuses.iter().copied().find(|s| *s != 0).unwrap_or(0),
+        Status::Worst => statuses.iter().copied().max().unwrap_or(0),
+        Status::Mask => statuses
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| **s != 0)
+            .fold(0, |mask, (i, _)| mask | 1 << i.min(7)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses() {
+        let statuses = [0, 3, 141, 1];
+        assert_eq!(3, combine(Status::First, &statuses));
+        assert_eq!(141, combine(Status::Worst, &statuses));
+        assert_eq!(0b1110, combine(Status::Mask, &statuses));
+        assert_eq!(0, combine(Status::First, &[0, 0]));
+        assert_eq!(128, combine(Status::Mask, &[0, 0, 0, 0, 0, 0, 0, 0, 1]));
+        assert_eq!(129, combine(Status::Mask, &[1, 0, 0, 0, 0, 0, 0, 1, 1]));
     }
-    Ok(())
 }
@@ -0,0 +1,155 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use regex::bytes::Regex;
+
+// which command each line of input goes to, for the commands that don't get all of it
+pub enum Rule {
+    // --route: the first command whose pattern matches, otherwise the --default one if any
+    Route {
+        routes: Vec<(Regex, usize)>,
+        default: Option<usize>,
+    },
+    // --balance round-robin: each command in turn
+    RoundRobin {
+        workers: Vec<usize>,
+        next: usize,
+    },
+    // --balance hash:N: lines with the same Nth field always go to the same command
+    Hash {
+        field: usize,
+        workers: Vec<usize>,
+    },
+}
+
+pub struct Dispatch {
+    rule: Rule,
+    // the start of a line whose end is in a later chunk
+    partial: Vec<u8>,
+}
+
+impl Dispatch {
+    pub fn new(rule: Rule) -> Dispatch {
+        Dispatch {
+            rule,
+            partial: Vec::new(),
+        }
+    }
+
+    // appends each complete line in chunk to the batch of the command it goes to
+    pub fn split(&mut self, chunk: &[u8], batches: &mut [Vec<u8>]) {
+        let mut rest = chunk;
+        while let Some(end) = rest.iter().position(|b| *b == b'\n') {
+            let (line, after) = rest.split_at(end + 1);
+            rest = after;
+            if self.partial.is_empty() {
+                self.dispatch(line, batches);
+            } else {
+                let mut whole = std::mem::take(&mut self.partial);
+                whole.extend_from_slice(line);
+                self.dispatch(&whole, batches);
+            }
+        }
+        self.partial.extend_from_slice(rest);
+    }
+
+    // dispatches the last line when input doesn't end with a newline
+    pub fn finish(&mut self, batches: &mut [Vec<u8>]) {
+        if !self.partial.is_empty() {
+            let last = std::mem::take(&mut self.partial);
+            self.dispatch(&last, batches);
+        }
+    }
+
+    // stops balancing lines to a command that is gone; routed lines for it are dropped instead
+    pub fn retire(&mut self, command: usize) {
+        match &mut self.rule {
+            Rule::Route { .. } => {}
+            Rule::RoundRobin { workers, .. } | Rule::Hash { workers, .. } => {
+                workers.retain(|i| *i != command)
+            }
+        }
+    }
+
+    fn dispatch(&mut self, line: &[u8], batches: &mut [Vec<u8>]) {
+        let text = line.strip_suffix(b"\n").unwrap_or(line);
+        let target = match &mut self.rule {
+            Rule::Route { routes, default } => routes
+                .iter()
+                .find(|(pattern, _)| pattern.is_match(text))
+                .map(|(_, i)| *i)
+                .or(*default),
+            Rule::RoundRobin { workers, next } => {
+                let worker = (!workers.is_empty()).then(|| workers[*next % workers.len()]);
+                *next = next.wrapping_add(1);
+                worker
+            }
+            Rule::Hash { field, workers } => {
+                let key = text
+                    .split(u8::is_ascii_whitespace)
+                    .filter(|word| !word.is_empty())
+                    .nth(*field - 1)
+                    .unwrap_or_default();
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                let n = workers.len() as u64;
+                (n > 0).then(|| workers[(hasher.finish() % n) as usize])
+            }
+        };
+        if let Some(i) = target {
+            batches[i].extend_from_slice(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route() {
+        let mut dispatch = Dispatch::new(Rule::Route {
+            routes: vec![
+                (Regex::new("^ERROR").unwrap(), 0),
+                (Regex::new("WARN|ERROR").unwrap(), 1),
+            ],
+            default: Some(2),
+        });
+        let mut batches = vec![Vec::new(); 3];
+        dispatch.split(b"ERROR a\nWARN b\nin", &mut batches);
+        dispatch.split(b"fo c\nan ERROR", &mut batches);
+        dispatch.finish(&mut batches);
+        assert_eq!(b"ERROR a\n", &batches[0][..]);
+        assert_eq!(b"WARN b\nan ERROR", &batches[1][..]);
+        assert_eq!(b"info c\n", &batches[2][..]);
+    }
+
+    #[test]
+    fn balance() {
+        let mut dispatch = Dispatch::new(Rule::RoundRobin {
+            workers: vec![0, 1, 2],
+            next: 0,
+        });
+        let mut batches = vec![Vec::new(); 3];
+        dispatch.split(b"a\nb\nc\nd\n", &mut batches);
+        dispatch.retire(1);
+        dispatch.split(b"e\nf\n", &mut batches);
+        assert_eq!(b"a\nd\ne\n", &batches[0][..]);
+        assert_eq!(b"b\n", &batches[1][..]);
+        assert_eq!(b"c\nf\n", &batches[2][..]);
+
+        let mut dispatch = Dispatch::new(Rule::Hash {
+            field: 2,
+            workers: vec![0, 1],
+        });
+        let mut batches = vec![Vec::new(); 2];
+        dispatch.split(b"1 x\n2  y\n3 x\n4 y z\n", &mut batches);
+        let batch_of = |line: &[u8]| {
+            batches
+                .iter()
+                .position(|batch| batch.windows(line.len()).any(|w| w == line))
+        };
+        assert_eq!(batch_of(b"1 x\n"), batch_of(b"3 x\n"));
+        assert_eq!(batch_of(b"2  y\n"), batch_of(b"4 y z\n"));
+    }
+}
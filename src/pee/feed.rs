@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+
+// input waiting to be written to one command by a thread of its own, so that a slow command
+// only holds up the others once it falls --max-lag bytes behind
+pub struct Feed {
+    queue: Arc<Queue>,
+    writer: Option<JoinHandle<()>>,
+}
+
+// shared by every feed, so pee can wait for whichever writer gets on first
+#[derive(Default)]
+pub struct Progress {
+    writes: Mutex<u64>,
+    changed: Condvar,
+}
+
+struct Queue {
+    pending: Mutex<Pending>,
+    changed: Condvar,
+    progress: Arc<Progress>,
+}
+
+#[derive(Default)]
+struct Pending {
+    chunks: VecDeque<Arc<[u8]>>,
+    // queued or being written
+    bytes: usize,
+    closed: bool,
+    error: Option<io::Error>,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Progress {
+    // ready is checked under the lock the writers take to report progress, so no wakeup is lost
+    // between checking and waiting
+    pub fn wait_until(&self, mut ready: impl FnMut() -> bool) {
+        let mut writes = self.writes.lock().unwrap_or_else(PoisonError::into_inner);
+        while !ready() {
+            writes = self
+                .changed
+                .wait(writes)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    // called with no Pending locked, since wait_until locks those under this one
+    fn notify(&self) {
+        *self.writes.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        self.changed.notify_all();
+    }
+}
+
+impl Feed {
+    // opens the output on the writer thread, since that may wait, as for a fifo
+    pub fn spawn<W, F>(open: F, progress: &Arc<Progress>) -> Feed
+    where
+        W: Write,
+        F: FnOnce() -> io::Result<W> + Send + 'static,
+    {
+        let queue = Arc::new(Queue {
+            pending: Mutex::new(Pending::default()),
+            changed: Condvar::new(),
+            progress: Arc::clone(progress),
+        });
+        let writer = {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || match open() {
+                Ok(output) => write_chunks(&queue, output),
+                Err(e) => fail(&queue, e),
+            })
+        };
+        Feed {
+            queue,
+            writer: Some(writer),
+        }
+    }
+
+    // waits for the writer to get within max_lag bytes of the end of the chunk; a chunk is
+    // always queued when nothing else is, however large it is
+    pub fn send(&self, chunk: &Arc<[u8]>, max_lag: usize) -> io::Result<()> {
+        let mut pending = self.queue.lock();
+        loop {
+            if let Some(e) = pending.error.take() {
+                return Err(e);
+            }
+            if pending.bytes == 0 || pending.bytes + chunk.len() <= max_lag {
+                pending.bytes += chunk.len();
+                pending.chunks.push_back(Arc::clone(chunk));
+                self.queue.changed.notify_all();
+                return Ok(());
+            }
+            pending = self
+                .queue
+                .changed
+                .wait(pending)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    // queues the chunk however far behind the writer is
+    pub fn queue(&self, chunk: &Arc<[u8]>) -> io::Result<()> {
+        self.send(chunk, usize::MAX)
+    }
+
+    // bytes queued or being written
+    pub fn backlog(&self) -> usize {
+        self.queue.lock().bytes
+    }
+
+    // the writer finishes what is queued, then closes the command's stdin
+    pub fn close(&self) {
+        self.queue.lock().closed = true;
+        self.queue.changed.notify_all();
+    }
+
+    // waits for the writer after close, returning the error it stopped on if any
+    pub fn join(&mut self) -> Option<io::Error> {
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+        self.queue.lock().error.take()
+    }
+
+    // drops whatever is queued and stops waiting for the writer, which may be stuck writing
+    // to a command that is not reading
+    pub fn abandon(&mut self) {
+        let mut pending = self.queue.lock();
+        pending.bytes -= pending.chunks.drain(..).map(|c| c.len()).sum::<usize>();
+        pending.closed = true;
+        self.queue.changed.notify_all();
+        self.writer = None;
+    }
+}
+
+fn write_chunks(queue: &Queue, mut output: impl Write) {
+    loop {
+        let chunk = {
+            let mut pending = queue.lock();
+            loop {
+                match pending.chunks.pop_front() {
+                    Some(chunk) => break chunk,
+                    None if pending.closed => return,
+                    None => {
+                        pending = queue
+                            .changed
+                            .wait(pending)
+                            .unwrap_or_else(PoisonError::into_inner)
+                    }
+                }
+            }
+        };
+        let result = output.write_all(&chunk);
+        queue.lock().bytes -= chunk.len();
+        if let Err(e) = result {
+            fail(queue, e);
+            return;
+        }
+        queue.changed.notify_all();
+        queue.progress.notify();
+    }
+}
+
+fn fail(queue: &Queue, e: io::Error) {
+    let mut pending = queue.lock();
+    pending.error = Some(e);
+    pending.bytes -= pending.chunks.drain(..).map(|c| c.len()).sum::<usize>();
+    drop(pending);
+    queue.changed.notify_all();
+    queue.progress.notify();
+}
@@ -0,0 +1,64 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::ChildStdout;
+use std::thread::{self, JoinHandle};
+
+// reads a command's stdout on a thread of its own; with a prefix, each line is tagged and then
+// either written straight to our stdout or, with serialize, kept with the rest of the output
+// to be written once the command finishes
+pub fn collect(
+    output: ChildStdout,
+    prefix: Option<Vec<u8>>,
+    serialize: bool,
+) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(output);
+        let mut kept = Vec::new();
+        let Some(prefix) = prefix else {
+            let _ = reader.read_to_end(&mut kept);
+            return kept;
+        };
+        let mut line = Vec::new();
+        let mut tagged = Vec::new();
+        while let Ok(1..) = reader.read_until(b'\n', &mut line) {
+            tagged.extend_from_slice(&prefix);
+            tagged.extend_from_slice(&line);
+            if !line.ends_with(b"\n") {
+                tagged.push(b'\n');
+            }
+            if serialize {
+                kept.append(&mut tagged);
+            } else {
+                // one write per line keeps lines from different commands whole; once stdout
+                // is gone the rest is read and thrown away, so the command never blocks on it
+                let _ = io::stdout().lock().write_all(&tagged);
+                tagged.clear();
+            }
+            line.clear();
+        }
+        kept
+    })
+}
+
+// splits label=command, where a label is a word of letters, digits, _ and -
+pub fn label(command: &[u8]) -> Option<(&[u8], &[u8])> {
+    let eq = command.iter().position(|b| *b == b'=')?;
+    let label = &command[..eq];
+    let word = |b: &u8| b.is_ascii_alphanumeric() || *b == b'_' || *b == b'-';
+    (!label.is_empty() && label.iter().all(word)).then(|| (label, &command[eq + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            Some((&b"up"[..], &b"tr a-z A-Z"[..])),
+            label(b"up=tr a-z A-Z")
+        );
+        assert_eq!(None, label(b"awk -v x=1 '{ print x }'"));
+        assert_eq!(None, label(b"=cat"));
+        assert_eq!(Some((&b"count"[..], &b""[..])), label(b"count="));
+    }
+}
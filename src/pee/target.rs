@@ -0,0 +1,68 @@
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+// where a copy of stdin goes: a shell command, or a file written to directly
+pub enum Target {
+    Command(OsString),
+    // >file, or >>file to append
+    File { path: PathBuf, append: bool },
+    // fifo:path, an existing named pipe
+    Fifo(PathBuf),
+}
+
+impl Target {
+    pub fn parse(arg: &OsStr) -> Target {
+        let bytes = arg.as_bytes();
+        let path = |rest: &[u8]| PathBuf::from(OsStr::from_bytes(rest.trim_ascii_start()));
+        if let Some(rest) = bytes.strip_prefix(b">>") {
+            Target::File {
+                path: path(rest),
+                append: true,
+            }
+        } else if let Some(rest) = bytes.strip_prefix(b">") {
+            Target::File {
+                path: path(rest),
+                append: false,
+            }
+        } else if let Some(rest) = bytes.strip_prefix(b"fifo:") {
+            Target::Fifo(path(rest))
+        } else {
+            Target::Command(arg.to_os_string())
+        }
+    }
+
+    // opening a fifo waits for a reader, so this is left to the thread that writes to it
+    pub fn open(&self) -> io::Result<File> {
+        match self {
+            Target::Command(_) => unreachable!("commands are spawned, not opened"),
+            Target::File { path, append } => OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(*append)
+                .truncate(!*append)
+                .open(path),
+            Target::Fifo(path) => OpenOptions::new().write(true).open(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn targets() {
+        let parse = |arg: &str| match Target::parse(OsStr::new(arg)) {
+            Target::Command(command) => format!("command {}", command.display()),
+            Target::File { path, append } => format!("file {} {append}", path.display()),
+            Target::Fifo(path) => format!("fifo {}", path.display()),
+        };
+        assert_eq!("file out.txt false", parse(">out.txt"));
+        assert_eq!("file out.txt true", parse(">> out.txt"));
+        assert_eq!("fifo /tmp/f", parse("fifo:/tmp/f"));
+        assert_eq!("command sort > out.txt", parse("sort > out.txt"));
+    }
+}
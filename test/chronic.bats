@@ -0,0 +1,84 @@
+setup() {
+  load 'test_helper/bats-support/load'
+  load 'test_helper/bats-assert/load'
+  DIR="$( cd "$( dirname "$BATS_TEST_FILENAME" )" >/dev/null 2>&1 && pwd )"
+  PATH="$( realpath "$DIR/../target/debug"):$PATH"
+  TEST_TMP=$(mktemp -d)
+}
+
+teardown() {
+  rm -rf "$TEST_TMP"
+}
+
+@test "Stays quiet when the command succeeds" {
+  run chronic sh -c 'echo out; echo err >&2'
+  assert_success
+  assert_output ""
+}
+
+@test "Shows output and exit code when the command fails" {
+  run chronic sh -c 'echo out; echo err >&2; exit 3'
+  assert_failure 3
+  assert_line out
+  assert_line err
+}
+
+@test "Leaves no temporary files behind" {
+  run env TMPDIR="$TEST_TMP" chronic sh -c 'ls -A "$TMPDIR"; echo done; exit 1'
+  assert_failure 1
+  assert_output done
+  assert [ -z "$(ls -A "$TEST_TMP")" ]
+}
+
+@test "Keeps the last bytes of each stream with --tail" {
+  run chronic --tail 10 sh -c 'seq 100; seq 200 >&2; exit 1'
+  assert_failure 1
+  assert_output "$(printf '98\n99\n100\n8\n199\n200')"
+}
+
+@test "Keeps the last lines of each stream with --tail" {
+  run chronic --tail=2l sh -c 'seq 100; seq 200 >&2; exit 1'
+  assert_failure 1
+  assert_output "$(printf '99\n100\n199\n200')"
+}
+
+@test "Still triggers on stderr with --tail" {
+  run chronic -e --tail 2 sh -c 'printf err >&2'
+  assert_success
+  assert_output "rr"
+}
+
+@test "A large --tail only costs what the command writes" {
+  run sh -c "ulimit -v 1000000; chronic --tail 1T sh -c 'echo out; exit 1'"
+  assert_failure 1
+  assert_output out
+}
+
+@test "--tail in lines keeps a bounded end of a line that never ends" {
+  run sh -c "chronic --tail 2l sh -c 'head -c 1000000 /dev/zero; exit 1' | wc -c"
+  assert_success
+  assert_output 131072
+}
+
+@test "Rejects an empty tail" {
+  run chronic --tail 0 true
+  assert_failure 1
+}
+
+@test "Replays stdout and stderr in the order they were written" {
+  run chronic sh -c 'echo one; sleep 0.1; echo two >&2; sleep 0.1; echo three; exit 1'
+  assert_failure 1
+  assert_output "$(printf 'one\ntwo\nthree')"
+}
+
+@test "Labels each stream as it changes with -v" {
+  run chronic -v sh -c 'echo one; sleep 0.1; echo two >&2; sleep 0.1; echo three; exit 2'
+  assert_failure 2
+  assert_output "$(printf 'STDOUT:\none\nSTDERR:\ntwo\nSTDOUT:\nthree\n\nRETVAL: 2')"
+}
+
+@test "Keeps the order with --tail" {
+  run chronic --tail 2l sh -c 'seq 3; sleep 0.1; echo err >&2; sleep 0.1; echo out; exit 1'
+  assert_failure 1
+  assert_output "$(printf '3\nerr\nout')"
+}
@@ -0,0 +1,366 @@
+setup() {
+  load 'test_helper/bats-support/load'
+  load 'test_helper/bats-assert/load'
+  DIR="$( cd "$( dirname "$BATS_TEST_FILENAME" )" >/dev/null 2>&1 && pwd )"
+  PATH="$( realpath "$DIR/../target/debug"):$PATH"
+  TEST_IN=$(mktemp)
+  TEST_OUT=$(mktemp -u)
+}
+
+teardown() {
+  rm -f "$TEST_IN" "$TEST_OUT"
+}
+
+@test "joblog records one line per finished job" {
+  parallel -j 2 --joblog "$TEST_OUT" -- 'true' 'exit 3' 'kill -9 $$' || true
+  run cut -f 1,4,5,6 "$TEST_OUT"
+  assert_line --index 0 "Seq	Exitval	Signal	Command"
+  assert_line "1	0	0	sh -c true"
+  assert_line "2	3	0	sh -c 'exit 3'"
+  assert_line "3	0	9	sh -c 'kill -9 \$\$'"
+}
+
+@test "resume skips jobs that already succeeded" {
+  parallel --joblog "$TEST_OUT" -- 'true' 'false' || true
+  parallel --joblog "$TEST_OUT" --resume -- 'exit 5' 'true'
+  run cut -f 1,4 "$TEST_OUT"
+  assert_output "Seq	Exitval
+1	0
+2	1
+2	0"
+}
+
+@test "resume-failed only reruns failed jobs" {
+  parallel --joblog "$TEST_OUT" -- 'true' 'false' || true
+  parallel --joblog "$TEST_OUT" --resume-failed -- 'exit 5' 'true' 'exit 7'
+  run cut -f 1,4 "$TEST_OUT"
+  assert_output "Seq	Exitval
+1	0
+2	1
+2	0"
+}
+
+@test "retries requeue a failing job and log each attempt" {
+  run parallel --retries 2 --joblog "$TEST_OUT" -- "echo x >> '$TEST_IN'; [ \$(wc -l < '$TEST_IN') -ge 2 ]"
+  assert_success
+  run cut -f 1,4 "$TEST_OUT"
+  assert_output "Seq	Exitval
+1	1
+1	0"
+}
+
+@test "retries give up after N attempts" {
+  run parallel --retries 2 --retry-delay 0.1 --joblog "$TEST_OUT" -- 'exit 3'
+  assert_failure 1
+  run cut -f 1,4 "$TEST_OUT"
+  assert_output "Seq	Exitval
+1	3
+1	3
+1	3"
+}
+
+@test "a retry delay too long to wait out fails the job at once" {
+  run timeout 10 parallel --retry-delay 1e19 --retries 1 -- false
+  assert_failure 1
+}
+
+@test "timeout kills the whole process group of a hung job" {
+  run parallel --timeout 0.5 --joblog "$TEST_OUT" -- "sh -c 'sleep 30; echo leaked >> $TEST_IN' & wait"
+  assert_failure
+  run cut -f 4,5 "$TEST_OUT"
+  assert_line --index 1 "0	15"
+  sleep 0.2
+  run pgrep -f "sleep 30; echo leaked >> $TEST_IN"
+  assert_failure
+}
+
+@test "jobs do not read a terminal they cannot get" {
+  command -v script >/dev/null || skip "needs script(1) for a pty"
+  run timeout 10 script -qec "parallel -- 'read x; echo got \$x'" /dev/null < /dev/null
+  assert_success
+  assert_output --partial got
+}
+
+@test "a stopped job is reported and terminated instead of waited on" {
+  run timeout 10 parallel -- 'kill -STOP $$; echo never'
+  assert_failure 1
+  assert_output "parallel: job 1 was stopped by signal 19, terminating it"
+}
+
+@test "timeouts too long to reach are no limit" {
+  run parallel --timeout 1e19 -- true
+  assert_success
+  run parallel -j 1 --timeout 1000000% -- true true true true
+  assert_success
+}
+
+@test "timeout escalates to SIGKILL when SIGTERM is ignored" {
+  run parallel --timeout 0.2 --joblog "$TEST_OUT" -- 'trap "" TERM; sleep 30'
+  assert_failure
+  run cut -f 4,5 "$TEST_OUT"
+  assert_line --index 1 "0	9"
+}
+
+@test "exit status is the number of failed jobs" {
+  run parallel -j 4 -- 'exit 1' 'exit 2' 'true' 'kill -9 $$'
+  assert_failure 3
+}
+
+@test "exit status is capped at 101" {
+  run parallel -j 8 -- $(yes false | head -120)
+  assert_failure 101
+}
+
+@test "halt soon stops starting jobs and waits for running ones" {
+  run parallel -j 2 --halt soon,fail=1 -- 'sleep 0.2; exit 4' 'sleep 0.5; echo finished' 'echo started'
+  assert_failure 4
+  assert_line "finished"
+  refute_line "started"
+}
+
+@test "halt now kills running jobs" {
+  run parallel -j 2 --halt now,fail=1 -- 'sleep 0.2; exit 4' 'sleep 5; echo finished' 'echo started'
+  assert_failure 4
+  refute_line "finished"
+  refute_line "started"
+}
+
+@test "halt on success exits zero" {
+  run parallel -j 2 --halt now,success=1 -- 'sleep 0.2' 'sleep 5; exit 1'
+  assert_success
+}
+
+@test "progress goes to stderr and ends with a summary line" {
+  parallel -j 2 --eta -- 'sleep 0.1' 'exit 1' 'true' 2> "$TEST_OUT" >/dev/null || true
+  run tail -n 1 "$TEST_OUT"
+  assert_output --regexp '^parallel: 3 done, 0 running, 0 left, avg [0-9.]+s, eta 0.0s$'
+}
+
+@test "memfree and cpu-idle start jobs when resources allow" {
+  run parallel --memfree 1K --cpu-idle 0 -- 'echo ran'
+  assert_success
+  assert_line "ran"
+}
+
+@test "memfree holds off jobs while memory is short" {
+  run timeout 2 parallel --memfree 1000T -- 'echo ran'
+  assert_failure 124
+  refute_line "ran"
+}
+
+@test "replacement strings expand per argument" {
+  run parallel -j 1 -i echo {} {.}.png {/} {//} {/.} {#} {%} -- dir/photo.jpg plain
+  assert_success
+  assert_line "dir/photo.jpg dir/photo.png photo.jpg dir photo 1 1"
+  assert_line "plain plain.png plain . plain 2 1"
+}
+
+@test "replacement strings repeat a word for each argument in a chunk" {
+  run parallel -i -n 2 echo x{} -- a b
+  assert_line "xa xb"
+}
+
+@test "multiple argument sources run every combination" {
+  run parallel -j 1 -i echo {1}{2} ::: a b ::: x y
+  assert_success
+  assert_line "ax"
+  assert_line "ay"
+  assert_line "bx"
+  assert_line "by"
+}
+
+@test "linked argument sources pair up element-wise" {
+  run parallel -j 1 -i echo {1}{2} ::: a b :::+ x y
+  assert_line "ax"
+  assert_line "by"
+  refute_line "ay"
+  refute_line "bx"
+}
+
+@test "dry run prints shell-quoted commands without running them" {
+  run parallel --dry-run -i touch "$TEST_OUT" {} -- "it's" 'a b' plain
+  assert_success
+  assert_output "touch $TEST_OUT 'it'\\''s'
+touch $TEST_OUT 'a b'
+touch $TEST_OUT plain"
+  assert [ ! -e "$TEST_OUT" ]
+}
+
+@test "dry run output can be piped to a shell" {
+  run sh -c "parallel --dry-run -i printf '%s|' {} -- \"it's\" 'a  b' '\$HOME' | sh"
+  assert_output "it's|a  b|\$HOME|"
+}
+
+@test "SIGTERM is forwarded to running jobs and their children" {
+  parallel -j 2 -- "sh -c 'sleep 1; touch $TEST_OUT' & wait" 'sleep 30' "touch $TEST_OUT" &
+  pid=$!
+  sleep 0.3
+  kill -TERM "$pid"
+  status=0
+  wait "$pid" || status=$?
+  assert_equal "$status" 143
+  sleep 1.2
+  assert [ ! -e "$TEST_OUT" ]
+}
+
+@test "SIGHUP is forwarded to running jobs" {
+  parallel -- "sh -c 'sleep 1; touch $TEST_OUT' & wait" &
+  pid=$!
+  sleep 0.3
+  kill -HUP "$pid"
+  status=0
+  wait "$pid" || status=$?
+  assert_equal "$status" 129
+  sleep 1.2
+  assert [ ! -e "$TEST_OUT" ]
+}
+
+@test "an error in the pool stops the running jobs before exiting" {
+  results=$(mktemp -d)
+  touch "$results/2"
+  run parallel --results "$results" -j 2 -- "sleep 1; touch $TEST_OUT" true
+  rm -rf "$results"
+  assert_failure
+  sleep 1.2
+  assert [ ! -e "$TEST_OUT" ]
+}
+
+@test "a second SIGINT kills jobs that ignore the first" {
+  parallel -- 'trap "" INT TERM; sleep 30' &
+  pid=$!
+  sleep 0.3
+  kill -INT "$pid"
+  sleep 0.3
+  kill -INT "$pid"
+  run timeout 2 tail --pid="$pid" -f /dev/null
+  assert_success
+}
+
+@test "jobs get their sequence number and slot in the environment" {
+  run parallel -j 2 -- 'echo "$PARALLEL_SEQ:$PARALLEL_JOBSLOT"; sleep 0.3' 'echo "$PARALLEL_SEQ:$PARALLEL_JOBSLOT"' 'echo "$PARALLEL_SEQ:$PARALLEL_JOBSLOT"'
+  assert_success
+  assert_line "1:1"
+  assert_line "2:2"
+  assert_line "3:2"
+}
+
+@test "--shell runs the command as shell code with quoted arguments" {
+  run parallel -j 1 --shell 'echo | printf "[%s]\n"' -- "a  b" "it's" "$(printf 'x\ny')" '$HOME'
+  assert_success
+  assert_output "$(printf '[a  b]\n[it'"'"'s]\n[x\ny]\n[$HOME]')"
+}
+
+@test "--shell quotes replacement strings but not the command" {
+  run parallel -j 1 --shell -i 'echo {.} > '"$TEST_OUT"'; cat '"$TEST_OUT" -- 'a b;c.txt'
+  assert_success
+  assert_output "a b;c"
+}
+
+@test "--shell-path picks the interpreter" {
+  run parallel --shell-path bash -- 'echo "${BASH_VERSION:+bash}"'
+  assert_success
+  assert_output "bash"
+}
+
+@test "--jobs-file sets the number of jobs" {
+  echo 2 > "$TEST_IN"
+  run parallel --jobs-file "$TEST_IN" -- 'echo "$PARALLEL_JOBSLOT"; sleep 0.5' 'echo "$PARALLEL_JOBSLOT"; sleep 0.5'
+  assert_success
+  assert_line "1"
+  assert_line "2"
+}
+
+@test "--jobs-file is read again when it changes" {
+  echo 1 > "$TEST_IN"
+  parallel --jobs-file "$TEST_IN" -- 'sleep 1' 'echo "$PARALLEL_JOBSLOT"' > "$TEST_OUT" 2>/dev/null &
+  pid=$!
+  sleep 0.3
+  echo 2 > "$TEST_IN"
+  wait "$pid"
+  assert_equal "$(cat "$TEST_OUT")" "2"
+}
+
+@test "--jobs-file must hold a positive integer" {
+  echo many > "$TEST_IN"
+  run parallel --jobs-file "$TEST_IN" -- true
+  assert_failure
+  assert_output --partial "must hold a positive integer"
+}
+
+@test "SIGUSR1 raises the number of jobs" {
+  parallel -j 1 -- 'sleep 1' 'echo "$PARALLEL_JOBSLOT"' > "$TEST_OUT" 2>/dev/null &
+  pid=$!
+  sleep 0.3
+  kill -USR1 "$pid"
+  wait "$pid"
+  assert_equal "$(cat "$TEST_OUT")" "2"
+}
+
+@test "SIGUSR2 lowers the number of jobs without killing any" {
+  parallel -j 2 -- 'sleep 1; echo done' 'sleep 0.5' 'echo "$PARALLEL_JOBSLOT"' > "$TEST_OUT" 2>/dev/null &
+  pid=$!
+  sleep 0.2
+  kill -USR2 "$pid"
+  wait "$pid"
+  assert_equal "$(cat "$TEST_OUT")" "$(printf 'done\n1')"
+}
+
+@test "thousands of short jobs never share a slot" {
+  rm "$TEST_IN"
+  mkdir "$TEST_IN"
+  run parallel -j 8 sh -c 'mkdir "$1/$PARALLEL_JOBSLOT" || exit 1; rmdir "$1/$PARALLEL_JOBSLOT"; echo "$PARALLEL_JOBSLOT"' sh "$TEST_IN" -- $(seq 3000)
+  rmdir "$TEST_IN"
+  assert_success
+  assert_equal "${#lines[@]}" 3000
+  assert_equal "$(printf '%s\n' "${lines[@]}" | sort -un | tr '\n' ' ')" "1 2 3 4 5 6 7 8 "
+}
+
+@test "short jobs keep flowing past a long one" {
+  start=$SECONDS
+  run parallel -j 3 -- 'sleep 2' $(yes true | head -n 1000)
+  assert_success
+  assert [ $((SECONDS - start)) -lt 5 ]
+}
+
+@test "--results keeps each job's output, exit value and command line" {
+  rm "$TEST_IN"
+  run parallel --results "$TEST_IN" -- 'echo out; echo err >&2' 'exit 3' 'kill -TERM $$'
+  assert_equal "$status" 2
+  assert_output ""
+  assert_equal "$(cat "$TEST_IN/1/stdout")" "out"
+  assert_equal "$(cat "$TEST_IN/1/stderr")" "err"
+  assert_equal "$(cat "$TEST_IN/1/exitval")" "0"
+  assert_equal "$(cat "$TEST_IN/1/command")" "sh -c 'echo out; echo err >&2'"
+  assert_equal "$(cat "$TEST_IN/2/exitval")" "3"
+  assert_equal "$(cat "$TEST_IN/3/exitval")" "143"
+  rm -r "$TEST_IN"
+}
+
+@test "--results keeps the last try of a retried job" {
+  rm "$TEST_IN"
+  run parallel --results "$TEST_IN" --retries 2 -- "echo try >> $TEST_OUT; cat $TEST_OUT; [ \$(wc -l < $TEST_OUT) -eq 3 ]"
+  assert_success
+  assert_equal "$(cat "$TEST_IN/1/stdout")" "$(printf 'try\ntry\ntry')"
+  assert_equal "$(cat "$TEST_IN/1/exitval")" "0"
+  rm -r "$TEST_IN"
+}
+
+@test "--pipe splits stdin into blocks of whole lines" {
+  seq 100000 > "$TEST_IN"
+  run sh -c "parallel --pipe --block 100k awk '{ s += \$1 } END { printf \"%.0f\\n\", s }' < '$TEST_IN'"
+  assert_success
+  assert_equal "${#lines[@]}" 6
+  assert_equal "$(printf '%s\n' "${lines[@]}" | awk '{ s += $1 } END { printf "%.0f", s }')" 5000050000
+}
+
+@test "--pipe feeds each block to its own job" {
+  run sh -c "seq 6 | parallel --pipe --block 4 -j 2 -i sh -c 'echo {#}: \$(cat)' | sort"
+  assert_success
+  assert_output "$(printf '1: 1 2\n2: 3 4\n3: 5 6')"
+}
+
+@test "--pipe carries on past jobs that stop reading" {
+  run sh -c "seq 1000000 | parallel --pipe --block 1M head -n 1 | wc -l"
+  assert_success
+  assert_output "7"
+}
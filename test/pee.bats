@@ -0,0 +1,181 @@
+setup() {
+  load 'test_helper/bats-support/load'
+  load 'test_helper/bats-assert/load'
+  DIR="$( cd "$( dirname "$BATS_TEST_FILENAME" )" >/dev/null 2>&1 && pwd )"
+  PATH="$( realpath "$DIR/../target/debug"):$PATH"
+  TEST_IN=$(mktemp)
+  TEST_OUT=$(mktemp -u)
+}
+
+teardown() {
+  rm -f "$TEST_IN" "$TEST_OUT"
+}
+
+@test "feeds stdin to every command" {
+  run sh -c 'echo test | pee cat cat'
+  assert_success
+  assert_output "$(printf 'test\ntest')"
+}
+
+@test "exits with the status of the first command that failed" {
+  run sh -c 'echo test | pee "cat >/dev/null" "exit 3" "exit 4"'
+  assert_equal "$status" 3
+}
+
+@test "maps a command killed by a signal to 128+N" {
+  run sh -c 'echo test | pee "kill -TERM \$\$"'
+  assert_equal "$status" 143
+}
+
+@test "--status=worst exits with the highest status" {
+  run sh -c 'echo test | pee --status=worst "exit 3" "exit 7" "exit 4"'
+  assert_equal "$status" 7
+}
+
+@test "--status=mask sets a bit for each command that failed" {
+  run sh -c 'echo test | pee --status=mask true "exit 3" true "exit 1"'
+  assert_equal "$status" 10
+}
+
+@test "--status=mask folds failures past the eighth command into the top bit" {
+  run sh -c 'echo test | pee --status=mask true true true true true true true true "exit 5"'
+  assert_equal "$status" 128
+}
+
+@test "a slow command does not hold up the others" {
+  run sh -c 'seq 100000 | pee "sleep 1; cat >/dev/null; echo slow" "wc -l"'
+  assert_success
+  assert_output "$(printf '100000\nslow')"
+}
+
+@test "--on-lag=block loses no input" {
+  run sh -c 'seq 1000000 | pee --max-lag 64K "sleep 0.5; wc -l" "wc -l"'
+  assert_success
+  assert_output "$(printf '1000000\n1000000')"
+}
+
+@test "--on-lag=drop skips input for a command that fell behind" {
+  run sh -c 'seq 1000000 | pee --on-lag drop "sleep 0.5; wc -l" "wc -l" 2>/dev/null'
+  assert_success
+  assert_line --index 0 "1000000"
+  assert [ "${lines[1]}" -lt 1000000 ]
+}
+
+@test "--on-lag=kill kills a command that fell behind" {
+  run sh -c 'seq 1000000 | pee --on-lag kill "sleep 10" "wc -l"'
+  assert_equal "$status" 137
+  assert_line "pee: sleep 10: fell behind, killing it"
+  assert_line "1000000"
+}
+
+@test "--on-lag=kill and drop leave equally fast commands alone, however fast the input" {
+  run sh -c "head -c 50M /dev/zero | pee --max-lag 8M --on-lag kill 'sleep 0.3; wc -c' 'sleep 0.3; wc -c'"
+  assert_success
+  assert_output "$(printf '52428800\n52428800')"
+  run sh -c "head -c 50M /dev/zero | pee --max-lag 8M --on-lag drop 'sleep 0.3; wc -c' 'sleep 0.3; wc -c'"
+  assert_success
+  assert_output "$(printf '52428800\n52428800')"
+}
+
+@test "--on-lag=kill kills everything the command started" {
+  run sh -c "seq 1000000 | pee --on-lag kill '(sleep 1; touch $TEST_OUT) | cat' 'wc -l'"
+  assert_equal "$status" 137
+  assert_line "1000000"
+  sleep 1.2
+  assert [ ! -e "$TEST_OUT" ]
+}
+
+@test "--serialize writes each command's output in command order" {
+  run sh -c 'seq 3 | pee --serialize "sleep 0.3; sed s/^/a/" "sed s/^/b/"'
+  assert_success
+  assert_output "$(printf 'a1\na2\na3\nb1\nb2\nb3')"
+}
+
+@test "--prefix tags each line with the command's number or label" {
+  run sh -c 'printf "x\nno newline" | pee --prefix --ordered cat "up=tr a-z A-Z"'
+  assert_success
+  assert_output "$(printf '1: x\n1: no newline\nup: X\nup: NO NEWLINE')"
+}
+
+@test "writes to >file and >>file targets alongside commands" {
+  echo old > "$TEST_IN"
+  echo old > "$TEST_OUT"
+  run sh -c "seq 3 | pee '>$TEST_IN' '>> $TEST_OUT' 'wc -l'"
+  assert_success
+  assert_output "3"
+  assert_equal "$(cat "$TEST_IN")" "$(seq 3)"
+  assert_equal "$(cat "$TEST_OUT")" "$(printf 'old\n1\n2\n3')"
+}
+
+@test "writes to a fifo: target once it has a reader" {
+  rm "$TEST_IN"
+  mkfifo "$TEST_IN"
+  (sleep 0.3; cat "$TEST_IN" > "$TEST_OUT") &
+  run sh -c "seq 3 | pee 'fifo:$TEST_IN' 'wc -l'"
+  wait
+  assert_success
+  assert_output "3"
+  assert_equal "$(cat "$TEST_OUT")" "$(seq 3)"
+}
+
+@test "a file target that cannot be opened is a write error" {
+  run sh -c "seq 3 | pee --no-ignore-write-errors '>/nonexistent/file' 'sleep 0.3'"
+  assert_failure
+  assert_line --partial "pee: >/nonexistent/file: No such file or directory"
+}
+
+@test "--route sends each line to the first command whose pattern matches" {
+  run sh -c "printf 'ERROR a\nWARN b\ninfo c\nWARN ERROR d\n' | pee --serialize --route '^ERROR=sed s/^/1:/' --route 'WARN|ERROR=sed s/^/2:/' --default 'sed s/^/3:/'"
+  assert_success
+  assert_output "$(printf '1:ERROR a\n2:WARN b\n2:WARN ERROR d\n3:info c')"
+}
+
+@test "--route drops lines that match nothing without --default, and broadcasts to the other commands" {
+  run sh -c "seq 10 | pee --serialize --route '^1=wc -l' 'wc -l'"
+  assert_success
+  assert_output "$(printf '10\n2')"
+}
+
+@test "--balance=round-robin sends each line to the next command in turn" {
+  run sh -c "seq 7 | pee --serialize --balance round-robin 'tr \"\n\" \" \"; echo' 'tr \"\n\" \" \"; echo'"
+  assert_success
+  assert_output "$(printf '1 3 5 7 \n2 4 6 ')"
+}
+
+@test "--balance=hash:FIELD keeps lines with the same field together" {
+  run sh -c "printf 'a x\nb y\nc x\nd y\ne x\n' | pee --serialize --balance hash:2 'cut -d\" \" -f2 | sort -u' 'cut -d\" \" -f2 | sort -u' 'cut -d\" \" -f2 | sort -u'"
+  assert_success
+  assert_equal "$(echo "$output" | sort | tr '\n' ' ')" "x y "
+}
+
+@test "a command that exits early is reported and the others keep getting input" {
+  run sh -c 'seq 100000 | pee "head -1" "wc -l"'
+  assert_success
+  assert_line "1"
+  assert_line "100000"
+  assert_line "pee: head -1: closed its input early"
+}
+
+@test "stops reading stdin once every command is gone" {
+  run timeout 5 sh -c 'yes | pee "head -1" "head -1"'
+  assert_success
+  assert_equal "$(printf '%s\n' "${lines[@]}" | grep -c '^y$')" 2
+}
+
+@test "--no-ignore-sigpipe exits 141 after feeding the commands that remain" {
+  run sh -c 'seq 100000 | pee --no-ignore-sigpipe "head -1" "wc -l"'
+  assert_equal "$status" 141
+  assert_line "100000"
+}
+
+@test "--no-ignore-write-errors exits 1 without killing the other commands" {
+  run sh -c "seq 3 | pee --no-ignore-write-errors '>/nonexistent/file' 'sleep 0.2; wc -l'"
+  assert_equal "$status" 1
+  assert_line "3"
+}
+
+@test "--no-ignore-write-errors also counts a command that exits early" {
+  run sh -c "seq 100000 | pee --no-ignore-write-errors 'head -1 >/dev/null' 'wc -l'"
+  assert_equal "$status" 1
+  assert_line "100000"
+}